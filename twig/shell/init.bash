@@ -0,0 +1,33 @@
+# twig shell integration for bash
+#
+# Add to ~/.bashrc:
+#   eval "$(twig init bash)"
+
+# twig escapes \, $ and ` for this (bash's default); PS0 below relies on it too
+shopt -s promptvars
+
+_twig_prompt() {
+    # Grab these first: every later command overwrites them
+    local -x TWIG_STATUS=$? TWIG_PIPESTATUS="${PIPESTATUS[*]}"
+    local jobs='\j'
+    local -x TWIG_JOBS=${jobs@P} TWIG_DURATION_MS=
+
+    # _twig_start is set by PS0 when a command runs (not for an empty line)
+    if [[ -n $_twig_start && -n $EPOCHREALTIME ]]; then
+        TWIG_DURATION_MS=$(( (10#0${EPOCHREALTIME//[!0-9]/} - _twig_start) / 1000 ))
+    fi
+    unset _twig_start
+
+    PS1=$(twig --mode bash --width "${COLUMNS:-0}")
+    PS2=$(twig --mode bash --kind continuation)
+
+    # Leave $? as it was for the rest of PROMPT_COMMAND
+    return "$TWIG_STATUS"
+}
+
+if [[ $PROMPT_COMMAND != *_twig_prompt* ]]; then
+    # First, so it sees the command's exit status
+    PROMPT_COMMAND="_twig_prompt${PROMPT_COMMAND:+; $PROMPT_COMMAND}"
+    # Expanded right before a command runs: start its timer, print nothing
+    PS0='${PS0:0:$((_twig_start=10#0${EPOCHREALTIME//[!0-9]/},0))}'"$PS0"
+fi
@@ -0,0 +1,44 @@
+# twig shell integration for fish
+#
+# Add to ~/.config/fish/config.fish:
+#   twig init fish | source
+#
+# This replaces fish_mode_prompt with an empty function, so fish's vi mode
+# indicator ([N], [I], ...) is no longer shown: use {vi_mode} in the format
+# instead. To keep fish's indicator, restore it after the line above:
+#   source $__fish_data_dir/functions/fish_mode_prompt.fish
+
+function fish_prompt
+    # Grab these first: every later command overwrites them
+    set -l last_pipestatus $pipestatus
+    set -lx TWIG_STATUS $status
+    set -lx TWIG_PIPESTATUS $last_pipestatus
+    set -lx TWIG_DURATION_MS $CMD_DURATION
+    set -lx TWIG_JOBS (count (jobs --group))
+    set -lx TWIG_KEYMAP
+    if test "$fish_key_bindings" = fish_vi_key_bindings
+        set TWIG_KEYMAP $fish_bind_mode
+    end
+
+    # Written straight to fish, not through (...), so multi-line prompts keep their newlines
+    twig --mode fish --width $COLUMNS
+end
+
+function fish_right_prompt
+    set -l last_pipestatus $pipestatus
+    set -lx TWIG_STATUS $status
+    set -lx TWIG_PIPESTATUS $last_pipestatus
+    set -lx TWIG_DURATION_MS $CMD_DURATION
+    set -lx TWIG_JOBS (count (jobs --group))
+    set -lx TWIG_KEYMAP
+    if test "$fish_key_bindings" = fish_vi_key_bindings
+        set TWIG_KEYMAP $fish_bind_mode
+    end
+
+    twig --mode fish --width $COLUMNS --right
+end
+
+# twig shows the mode as {vi_mode} (see the note at the top); with an empty
+# mode prompt fish also redraws the whole prompt when the mode changes
+function fish_mode_prompt
+end
@@ -0,0 +1,24 @@
+# twig shell integration for PowerShell
+#
+# Add to your $PROFILE:
+#   Invoke-Expression (& twig init pwsh | Out-String)
+
+function global:prompt {
+    # Grab these first: every later command overwrites them
+    $success = $?
+    $exitCode = $global:LASTEXITCODE
+
+    $env:TWIG_STATUS = if ($success) { 0 } elseif ($exitCode) { $exitCode } else { 1 }
+    $last = Get-History -Count 1
+    $env:TWIG_DURATION_MS = if ($last) { [int64]$last.Duration.TotalMilliseconds } else { '' }
+    $env:TWIG_JOBS = @(Get-Job -State Running).Count
+
+    # Native output arrives one line per element: put the newlines back
+    $prompt = (twig --mode pwsh --width $Host.UI.RawUI.WindowSize.Width) -join "`n"
+
+    # Running twig resets $LASTEXITCODE; keep the user's command's
+    $global:LASTEXITCODE = $exitCode
+    $prompt
+}
+
+Set-PSReadLineOption -ContinuationPrompt ((twig --mode pwsh --kind continuation) -join "`n")
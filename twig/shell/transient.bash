@@ -0,0 +1,15 @@
+# Redraw an accepted prompt in its compact format_transient form.
+# Bash has no hook for this, but PS0 is expanded after a command is read and
+# before it runs: move up over the prompt, clear it and print the transient
+# prompt followed by the command.
+_twig_transient() {
+    local cmd lines
+    # Lines the prompt takes up (the last one holds the command)
+    lines=$(( $(printf '%s' "$PS1" | wc -l) + 1 ))
+    cmd=$(HISTTIMEFORMAT= history 1 | sed 's/^ *[0-9]* *//')
+    printf '\e[%dA\e[J%s%s' "$lines" "$(twig --prompt --width "${COLUMNS:-0}" --transient)" "$cmd"
+}
+
+if [[ $PS0 != *_twig_transient* ]]; then
+    PS0+='$(_twig_transient)\n'
+fi
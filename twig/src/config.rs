@@ -1,7 +1,55 @@
+use crate::style::{ColorDepth, Palette};
+use crate::template::{Fragments, Priority};
+use crate::width::AmbiguousWidth;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Names of all provider sections
+pub const SECTIONS: [&str; 10] = ["time", "hostname", "cwd", "git", "ip", "battery", "exit", "cmd", "jobs", "vi"];
+
+/// Value -> style mapping on a provider section
+///
+/// Keys are exact values or glob patterns (`*`, `?`); `default` applies when
+/// nothing else matches. Example: `styles = { main = "green,bold", "release/*" = "red" }`
+pub type ValueStyles = HashMap<String, String>;
+
+/// A threshold rule on a provider section
+///
+/// ```toml
+/// [[battery.rules]]
+/// when = "percentage < 20"
+/// style = "red,bold"
+/// ```
+///
+/// Conditions compare a provider's raw numbers, not the formatted values.
+/// Names are relative to the section (`percentage` -> `battery_percentage`).
+/// The first matching rule of a variable wins.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Rule {
+    /// Condition such as `percentage < 20` or `status_staged >= 10`
+    pub when: String,
+    /// Style added on top of the variable's own style when the rule matches
+    #[serde(default)]
+    pub style: Option<String>,
+    /// Hide the variable when the rule matches
+    #[serde(default)]
+    pub hide: bool,
+    /// Variable the rule applies to (default: the one named in `when`)
+    #[serde(default)]
+    pub target: Option<String>,
+}
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Config {
+    /// Theme whose `[styles]` palette is loaded under the config's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    /// Named styles usable in place of colors: `warn = "red,bold"` -> `{x:warn}`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: Palette,
+    /// Reusable template pieces: `git = "{git_branch}..."` -> `{@git}`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fragments: Fragments,
     #[serde(default)]
     pub time: Option<TimeConfig>,
     #[serde(default)]
@@ -14,37 +62,61 @@ pub struct Config {
     pub ip: Option<IpConfig>,
     #[serde(default)]
     pub battery: Option<BatteryConfig>,
+    #[serde(default)]
+    pub exit: Option<ExitConfig>,
+    #[serde(default)]
+    pub cmd: Option<CmdConfig>,
+    #[serde(default)]
+    pub jobs: Option<JobsConfig>,
+    #[serde(default)]
+    pub vi: Option<ViConfig>,
     pub prompt: PromptConfig,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct TimeConfig {
     #[serde(default)]
     pub name: Option<String>,
     #[serde(default = "default_time_format")]
     pub format: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct HostnameConfig {
     #[serde(default)]
     pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct CwdConfig {
     #[serde(default)]
     pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct GitConfig {
     #[serde(default)]
     pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
     // Future: show_dirty, show_ahead_behind
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct IpConfig {
     #[serde(default)]
     pub name: Option<String>,
@@ -52,21 +124,98 @@ pub struct IpConfig {
     pub interface: Option<String>,
     #[serde(default)]
     pub prefer_ipv6: bool,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct BatteryConfig {
     #[serde(default)]
     pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+}
+
+/// `[exit]`: the last command's exit status
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ExitConfig {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+}
+
+/// `[cmd]`: how long the last command ran
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CmdConfig {
+    /// Shortest duration shown as `{cmd_duration}`, in milliseconds (default: 2000)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// `[jobs]`: background jobs of the shell
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct JobsConfig {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+}
+
+/// `[vi]`: what `{vi_mode}` shows in each vi mode (an empty string hides it)
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ViConfig {
+    /// Default: "[N]"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal: Option<String>,
+    /// Default: "[I]"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insert: Option<String>,
+    /// Default: "[V]"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visual: Option<String>,
+    /// Default: "[R]"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub styles: ValueStyles,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct PromptConfig {
+    /// Optional when `segments` are configured
+    #[serde(default)]
     pub format: String,
     #[serde(default)]
     pub format_wide: Option<String>,
     #[serde(default)]
     pub format_narrow: Option<String>,
+    /// Right-side prompt (zsh `RPROMPT`, tcsh `rprompt`, right-aligned for bash/raw)
+    /// Dropped before the left prompt is narrowed when both don't fit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format_right: Option<String>,
+    /// Compact form that replaces a prompt once its command has run (`twig --transient`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format_transient: Option<String>,
+    /// Continuation prompt for unfinished commands (PS2, tcsh prompt2); `twig --kind continuation`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuation: Option<String>,
+    /// Trace prompt printed before each `set -x` line (PS4); `twig --kind trace`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>,
+    /// Prompt of the `select` builtin (PS3); `twig --kind select`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub select: Option<String>,
     #[serde(default)]
     pub width_threshold: Option<u16>,
     /// Extra padding (in characters) when deciding to switch to narrow format
@@ -76,6 +225,65 @@ pub struct PromptConfig {
     /// - Negative: Allow prompt to overflow before switching (aggressive)
     #[serde(default)]
     pub padding: Option<i32>,
+    /// Color depth for truecolor/256-color styles: "truecolor", "256" or "16"
+    /// Default: detected from COLORTERM/TERM. Colors are downsampled to fit.
+    #[serde(default)]
+    pub color_depth: Option<ColorDepth>,
+    /// Width of East Asian "ambiguous" characters: "narrow" or "wide"
+    /// Default: narrow. Use "wide" if your terminal draws them in two columns.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ambiguous_width: Option<AmbiguousWidth>,
+    /// Emit OSC 8 hyperlinks for values styled `link` (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hyperlinks: Option<bool>,
+    /// Responsive formats, tried in order until one fits (replaces `format`,
+    /// `format_wide` and `format_narrow` when set)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub formats: Vec<FormatConfig>,
+    /// Powerline segments, rendered as a bar in front of `format`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<SegmentConfig>,
+    /// Separator between segments: "powerline" (default), "round", "ascii" or a custom string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub separator: Option<String>,
+}
+
+/// One `[[prompt.formats]]` entry
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FormatConfig {
+    pub format: String,
+    /// Only considered when the terminal is at least this wide
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_width: Option<u16>,
+    /// Only considered when the terminal is at most this wide
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u16>,
+}
+
+impl FormatConfig {
+    /// Whether the entry applies at this terminal width (any entry does when it is unknown)
+    pub fn accepts(&self, terminal_width: Option<u16>) -> bool {
+        let Some(width) = terminal_width else {
+            return true;
+        };
+        self.min_width.is_none_or(|min| width >= min) && self.max_width.is_none_or(|max| width <= max)
+    }
+}
+
+/// One `[[prompt.segments]]` entry
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SegmentConfig {
+    /// Template for the segment's content, e.g. `" {cwd|basename} "`
+    pub format: String,
+    /// Foreground color, optionally with attributes (`"black,bold"`)
+    #[serde(default)]
+    pub fg: Option<String>,
+    /// Background color
+    #[serde(default)]
+    pub bg: Option<String>,
+    /// Drop the whole segment when the prompt is too wide (lowest first)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
 }
 
 fn default_time_format() -> String {
@@ -83,6 +291,11 @@ fn default_time_format() -> String {
 }
 
 impl PromptConfig {
+    /// Whether `link` styles produce hyperlinks
+    pub fn hyperlinks_enabled(&self) -> bool {
+        self.hyperlinks.unwrap_or(true)
+    }
+
     /// Get the appropriate format string based on terminal width
     ///
     /// # Arguments
@@ -122,6 +335,31 @@ impl PromptConfig {
         // Fallback to default format (always available)
         &self.format
     }
+
+    /// Formats to try in order, most detailed first
+    ///
+    /// With `[[prompt.formats]]`: the entries whose `min_width`/`max_width`
+    /// admit the terminal width, or the last entry if none does. Otherwise the
+    /// format from [`get_format`](Self::get_format), followed by `format_narrow`
+    /// when switching dynamically (no `width_threshold`).
+    ///
+    /// The caller renders them in turn and keeps the first that fits.
+    pub fn candidate_formats(&self, terminal_width: Option<u16>) -> Vec<&str> {
+        if let Some(last) = self.formats.last() {
+            let candidates: Vec<&str> = self.formats
+                .iter()
+                .filter(|entry| entry.accepts(terminal_width))
+                .map(|entry| entry.format.as_str())
+                .collect();
+            return if candidates.is_empty() { vec![&last.format] } else { candidates };
+        }
+
+        let mut candidates = vec![self.get_format(terminal_width)];
+        if self.width_threshold.is_none() {
+            candidates.extend(self.format_narrow.as_deref());
+        }
+        candidates
+    }
 }
 
 impl Config {
@@ -133,6 +371,10 @@ impl Config {
             "git" => self.git.is_some(),
             "ip" => self.ip.is_some(),
             "battery" => self.battery.is_some(),
+            "exit" => self.exit.is_some(),
+            "cmd" => self.cmd.is_some(),
+            "jobs" => self.jobs.is_some(),
+            "vi" => self.vi.is_some(),
             _ => false,
         }
     }
@@ -140,23 +382,131 @@ impl Config {
     pub fn add_implicit_section(&mut self, section: String, _value: serde_json::Value) {
         match section.as_str() {
             "time" => self.time = Some(TimeConfig {
-                name: None,
-                format: "%H:%M:%S".to_string()
-            }),
-            "hostname" => self.hostname = Some(HostnameConfig { name: None }),
-            "cwd" => self.cwd = Some(CwdConfig { name: None }),
-            "git" => self.git = Some(GitConfig { name: None }),
-            "ip" => self.ip = Some(IpConfig {
-                name: None,
-                interface: None,
-                prefer_ipv6: false,
-            }),
-            "battery" => self.battery = Some(BatteryConfig {
-                name: None,
+                format: "%H:%M:%S".to_string(),
+                ..Default::default()
             }),
+            "hostname" => self.hostname = Some(HostnameConfig::default()),
+            "cwd" => self.cwd = Some(CwdConfig::default()),
+            "git" => self.git = Some(GitConfig::default()),
+            "ip" => self.ip = Some(IpConfig::default()),
+            "battery" => self.battery = Some(BatteryConfig::default()),
+            "exit" => self.exit = Some(ExitConfig::default()),
+            "cmd" => self.cmd = Some(CmdConfig::default()),
+            "jobs" => self.jobs = Some(JobsConfig::default()),
+            "vi" => self.vi = Some(ViConfig::default()),
             _ => {}
         }
     }
+
+    /// The section a variable belongs to
+    ///
+    /// Custom variable names (`[cwd] name = "dir"`) map back to their section;
+    /// everything else uses the prefix before the first underscore, the same
+    /// rule the provider registry uses (`git_branch` -> `git`).
+    pub fn section_for_variable<'a>(&self, variable: &'a str) -> &'a str {
+        let renamed = [
+            ("time", self.time.as_ref().and_then(|c| c.name.as_deref())),
+            ("hostname", self.hostname.as_ref().and_then(|c| c.name.as_deref())),
+            ("cwd", self.cwd.as_ref().and_then(|c| c.name.as_deref())),
+        ];
+        for (section, name) in renamed {
+            if name == Some(variable) {
+                return section;
+            }
+        }
+        variable.split('_').next().unwrap_or(variable)
+    }
+
+    /// The `styles` table of a section, if the section is configured
+    pub fn section_styles(&self, section: &str) -> Option<&ValueStyles> {
+        match section {
+            "time" => self.time.as_ref().map(|c| &c.styles),
+            "hostname" => self.hostname.as_ref().map(|c| &c.styles),
+            "cwd" => self.cwd.as_ref().map(|c| &c.styles),
+            "git" => self.git.as_ref().map(|c| &c.styles),
+            "ip" => self.ip.as_ref().map(|c| &c.styles),
+            "battery" => self.battery.as_ref().map(|c| &c.styles),
+            "exit" => self.exit.as_ref().map(|c| &c.styles),
+            "cmd" => self.cmd.as_ref().map(|c| &c.styles),
+            "jobs" => self.jobs.as_ref().map(|c| &c.styles),
+            "vi" => self.vi.as_ref().map(|c| &c.styles),
+            _ => None,
+        }
+    }
+
+    /// The `rules` of a section, if the section is configured
+    pub fn section_rules(&self, section: &str) -> Option<&[Rule]> {
+        match section {
+            "time" => self.time.as_ref().map(|c| c.rules.as_slice()),
+            "hostname" => self.hostname.as_ref().map(|c| c.rules.as_slice()),
+            "cwd" => self.cwd.as_ref().map(|c| c.rules.as_slice()),
+            "git" => self.git.as_ref().map(|c| c.rules.as_slice()),
+            "ip" => self.ip.as_ref().map(|c| c.rules.as_slice()),
+            "battery" => self.battery.as_ref().map(|c| c.rules.as_slice()),
+            "exit" => self.exit.as_ref().map(|c| c.rules.as_slice()),
+            "cmd" => self.cmd.as_ref().map(|c| c.rules.as_slice()),
+            "jobs" => self.jobs.as_ref().map(|c| c.rules.as_slice()),
+            "vi" => self.vi.as_ref().map(|c| c.rules.as_slice()),
+            _ => None,
+        }
+    }
+
+    /// Style mapped to a variable's value by its section's `styles` table
+    pub fn value_style(&self, variable: &str, value: &str) -> Option<&str> {
+        let styles = self.section_styles(self.section_for_variable(variable))?;
+        match_value_style(styles, value)
+    }
+}
+
+/// Find the style for `value` in a `styles` table
+///
+/// Precedence: exact match, then the longest matching glob pattern, then `default`.
+pub fn match_value_style<'a>(styles: &'a ValueStyles, value: &str) -> Option<&'a str> {
+    if let Some(style) = styles.get(value) {
+        return Some(style);
+    }
+
+    let glob = styles
+        .iter()
+        .filter(|(pattern, _)| is_glob(pattern) && glob_match(pattern, value))
+        // Longest pattern wins; ties broken by name so the result is stable
+        .max_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| b.cmp(a)));
+    if let Some((_, style)) = glob {
+        return Some(style);
+    }
+
+    styles.get("default").map(String::as_str)
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Match `text` against a glob pattern where `*` matches any run of
+/// characters (including `/`) and `?` matches exactly one character
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Iterative matching with backtracking to the last `*`
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 #[cfg(test)]
@@ -172,6 +522,7 @@ mod tests {
             format_narrow: None,
             width_threshold: Some(100),
             padding: None,
+            ..Default::default()
         };
 
         assert_eq!(prompt.get_format(Some(50)), "default");
@@ -188,6 +539,7 @@ mod tests {
             format_narrow: Some("narrow".to_string()),
             width_threshold: Some(100),
             padding: None,
+            ..Default::default()
         };
 
         // Below threshold - use narrow
@@ -211,6 +563,7 @@ mod tests {
             format_narrow: None,
             width_threshold: Some(100),
             padding: None,
+            ..Default::default()
         };
 
         // Below threshold - use default (no narrow configured)
@@ -234,6 +587,7 @@ mod tests {
             format_narrow: Some("narrow".to_string()),
             width_threshold: Some(100),
             padding: None,
+            ..Default::default()
         };
 
         // Below threshold - use narrow
@@ -257,6 +611,7 @@ mod tests {
             format_narrow: Some("narrow".to_string()),
             width_threshold: Some(80),
             padding: None,
+            ..Default::default()
         };
 
         assert_eq!(prompt.get_format(Some(50)), "narrow");
@@ -274,6 +629,7 @@ mod tests {
             format_narrow: Some("narrow".to_string()),
             width_threshold: None,
             padding: None,
+            ..Default::default()
         };
 
         // With no threshold, should return format_wide (for dynamic checking in main)
@@ -281,4 +637,143 @@ mod tests {
         assert_eq!(prompt.get_format(Some(150)), "wide");
         assert_eq!(prompt.get_format(None), "wide");
     }
+
+    #[test]
+    fn test_candidate_formats_legacy() {
+        let mut prompt = PromptConfig {
+            format: "default".to_string(),
+            format_wide: Some("wide".to_string()),
+            format_narrow: Some("narrow".to_string()),
+            ..Default::default()
+        };
+        // Dynamic switching tries wide, then narrow
+        assert_eq!(prompt.candidate_formats(Some(80)), ["wide", "narrow"]);
+
+        // A threshold picks exactly one
+        prompt.width_threshold = Some(100);
+        assert_eq!(prompt.candidate_formats(Some(80)), ["narrow"]);
+        assert_eq!(prompt.candidate_formats(Some(120)), ["wide"]);
+    }
+
+    #[test]
+    fn test_candidate_formats_list() {
+        let prompt: PromptConfig = toml::from_str(
+            r#"
+            format = "ignored"
+
+            [[formats]]
+            format = "full"
+            min_width = 120
+
+            [[formats]]
+            format = "no-ip"
+
+            [[formats]]
+            format = "cwd-git"
+            max_width = 100
+
+            [[formats]]
+            format = "cwd"
+            max_width = 60
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(prompt.candidate_formats(Some(150)), ["full", "no-ip"]);
+        assert_eq!(prompt.candidate_formats(Some(120)), ["full", "no-ip"]);
+        assert_eq!(prompt.candidate_formats(Some(80)), ["no-ip", "cwd-git"]);
+        assert_eq!(prompt.candidate_formats(Some(40)), ["no-ip", "cwd-git", "cwd"]);
+        // Unknown width: every entry, in order
+        assert_eq!(prompt.candidate_formats(None).len(), 4);
+    }
+
+    #[test]
+    fn test_candidate_formats_none_accepted() {
+        let prompt: PromptConfig = toml::from_str(
+            r#"
+            [[formats]]
+            format = "wide"
+            min_width = 100
+
+            [[formats]]
+            format = "medium"
+            min_width = 60
+            "#,
+        )
+        .unwrap();
+        // Falls back to the last (most compact) entry
+        assert_eq!(prompt.candidate_formats(Some(40)), ["medium"]);
+    }
+
+    fn styles(pairs: &[(&str, &str)]) -> ValueStyles {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_match_value_style() {
+        let map = styles(&[
+            ("main", "green,bold"),
+            ("release/*", "red"),
+            ("release/v1.*", "yellow"),
+            ("feature-?", "blue"),
+            ("default", "cyan"),
+        ]);
+
+        // Exact match
+        assert_eq!(match_value_style(&map, "main"), Some("green,bold"));
+        // Glob match, `*` crosses `/`
+        assert_eq!(match_value_style(&map, "release/2.0/rc1"), Some("red"));
+        // Longest matching pattern wins
+        assert_eq!(match_value_style(&map, "release/v1.4"), Some("yellow"));
+        // `?` matches exactly one character
+        assert_eq!(match_value_style(&map, "feature-x"), Some("blue"));
+        assert_eq!(match_value_style(&map, "feature-xy"), Some("cyan"));
+        // Default
+        assert_eq!(match_value_style(&map, "dev"), Some("cyan"));
+
+        // No default: no style
+        let map = styles(&[("main", "green")]);
+        assert_eq!(match_value_style(&map, "dev"), None);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("prod-*", "prod-web-1"));
+        assert!(glob_match("*-db-*", "eu-db-03"));
+        assert!(glob_match("a*b*c", "axxbyybzc"));
+        assert!(!glob_match("prod-*", "staging-web"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn test_value_style_lookup() {
+        let config: Config = toml::from_str(
+            r#"
+            [hostname]
+            styles = { skyfall = "magenta", default = "white" }
+
+            [cwd]
+            name = "dir"
+            styles = { "/tmp*" = "red" }
+
+            [git]
+            styles = { main = "green" }
+
+            [prompt]
+            format = "{hostname}"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.value_style("hostname", "skyfall"), Some("magenta"));
+        assert_eq!(config.value_style("hostname", "laptop"), Some("white"));
+        // Renamed variable maps back to its section
+        assert_eq!(config.value_style("dir", "/tmp/x"), Some("red"));
+        // Prefix-based sections
+        assert_eq!(config.value_style("git_branch", "main"), Some("green"));
+        // Sections without a styles table, or not configured
+        assert_eq!(config.value_style("time", "12:00"), None);
+        assert_eq!(config.value_style("battery_percentage", "50"), None);
+    }
 }
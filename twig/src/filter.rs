@@ -0,0 +1,223 @@
+// twig/src/filter.rs
+
+//! Value filters: `{cwd|basename}`, `{git_branch|truncate(20,"…")}`
+//!
+//! Filters transform a placeholder's value before it is styled and can be
+//! chained (`{cwd|basename|upper:green}`). Lengths are counted in terminal
+//! columns (see `width.rs`): `日本` is four columns wide, and a character is
+//! never cut in half.
+//!
+//! | Filter                  | Effect                                                  |
+//! |-------------------------|---------------------------------------------------------|
+//! | `basename`              | Last path component (`/home/me/src` → `src`)            |
+//! | `dirname`               | Everything before the last path component               |
+//! | `upper` / `lower`       | Change case                                             |
+//! | `trim`                  | Strip surrounding whitespace                            |
+//! | `truncate(n[, "…"])`    | Keep `n` columns, append the ellipsis if cut            |
+//! | `replace("a", "b")`     | Replace every `a` with `b`                              |
+//! | `pad_left(n[, " "])`    | Pad on the left to `n` columns                          |
+//! | `pad_right(n[, " "])`   | Pad on the right to `n` columns                         |
+//!
+//! Both the renderer and `--validate` go through [`check`], so an invalid
+//! filter is reported by `--validate` and skipped when rendering.
+
+use crate::template::Filter;
+use crate::width;
+
+/// Names of all filters, for error messages
+const NAMES: [&str; 9] = [
+    "basename", "dirname", "upper", "lower", "trim", "truncate", "replace", "pad_left", "pad_right",
+];
+
+/// Check a filter's name and arguments without applying it
+pub fn check(filter: &Filter) -> Result<(), String> {
+    apply(filter, "").map(|_| ())
+}
+
+/// Apply a single filter to a value
+pub fn apply(filter: &Filter, value: &str) -> Result<String, String> {
+    let args = &filter.args;
+    match filter.name.as_str() {
+        "basename" => {
+            arity(filter, 0, 0)?;
+            let trimmed = value.trim_end_matches('/');
+            if trimmed.is_empty() {
+                // "/" stays "/"
+                return Ok(value.to_string());
+            }
+            Ok(trimmed.rsplit('/').next().unwrap_or(trimmed).to_string())
+        }
+        "dirname" => {
+            arity(filter, 0, 0)?;
+            let trimmed = value.trim_end_matches('/');
+            Ok(match trimmed.rfind('/') {
+                Some(0) => "/".to_string(),
+                Some(index) => trimmed[..index].to_string(),
+                None => String::new(),
+            })
+        }
+        "upper" => {
+            arity(filter, 0, 0)?;
+            Ok(value.to_uppercase())
+        }
+        "lower" => {
+            arity(filter, 0, 0)?;
+            Ok(value.to_lowercase())
+        }
+        "trim" => {
+            arity(filter, 0, 0)?;
+            Ok(value.trim().to_string())
+        }
+        "truncate" => {
+            arity(filter, 1, 2)?;
+            let length = number_arg(filter, 0)?;
+            let ellipsis = args.get(1).map(String::as_str).unwrap_or("…");
+            if width::display_width(value) <= length {
+                return Ok(value.to_string());
+            }
+            let mut truncated = width::truncate(value, length).to_string();
+            truncated.push_str(ellipsis);
+            Ok(truncated)
+        }
+        "replace" => {
+            arity(filter, 2, 2)?;
+            if args[0].is_empty() {
+                return Err(format!("Filter '{}': text to replace must not be empty", filter.name));
+            }
+            Ok(value.replace(&args[0], &args[1]))
+        }
+        "pad_left" | "pad_right" => {
+            arity(filter, 1, 2)?;
+            let columns = number_arg(filter, 0)?;
+            let fill = match args.get(1) {
+                Some(fill) => {
+                    let mut chars = fill.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if width::display_width(fill) == 1 => c,
+                        _ => {
+                            return Err(format!(
+                                "Filter '{}': padding must be a single one-column character, got \"{}\"",
+                                filter.name, fill
+                            ))
+                        }
+                    }
+                }
+                None => ' ',
+            };
+            let padding: String =
+                std::iter::repeat_n(fill, columns.saturating_sub(width::display_width(value))).collect();
+            Ok(if filter.name == "pad_left" {
+                padding + value
+            } else {
+                value.to_string() + &padding
+            })
+        }
+        other => Err(format!(
+            "Unknown filter: '{}' (available: {})",
+            other,
+            NAMES.join(", ")
+        )),
+    }
+}
+
+/// Apply a chain of filters, skipping invalid ones
+pub fn apply_all(filters: &[Filter], value: &str) -> String {
+    filters.iter().fold(value.to_string(), |value, filter| {
+        apply(filter, &value).unwrap_or(value)
+    })
+}
+
+/// Check the number of arguments
+fn arity(filter: &Filter, min: usize, max: usize) -> Result<(), String> {
+    let count = filter.args.len();
+    if count >= min && count <= max {
+        return Ok(());
+    }
+    let expected = match (min, max) {
+        (0, 0) => "no arguments".to_string(),
+        (min, max) if min == max => format!("{} argument{}", min, if min == 1 { "" } else { "s" }),
+        (min, max) => format!("{} to {} arguments", min, max),
+    };
+    Err(format!("Filter '{}' takes {}, got {}", filter.name, expected, count))
+}
+
+/// Parse argument `index` as a non-negative integer
+fn number_arg(filter: &Filter, index: usize) -> Result<usize, String> {
+    let arg = &filter.args[index];
+    arg.parse()
+        .map_err(|_| format!("Filter '{}': expected a number, got \"{}\"", filter.name, arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Span;
+
+    fn filter(name: &str, args: &[&str]) -> Filter {
+        Filter {
+            name: name.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            span: Span::new(0, 0),
+        }
+    }
+
+    fn run(name: &str, args: &[&str], value: &str) -> String {
+        apply(&filter(name, args), value).unwrap()
+    }
+
+    #[test]
+    fn test_path_filters() {
+        assert_eq!(run("basename", &[], "/home/me/src/twig"), "twig");
+        assert_eq!(run("basename", &[], "/home/me/"), "me");
+        assert_eq!(run("basename", &[], "/"), "/");
+        assert_eq!(run("dirname", &[], "/home/me/src"), "/home/me");
+        assert_eq!(run("dirname", &[], "/home"), "/");
+        assert_eq!(run("dirname", &[], "relative"), "");
+    }
+
+    #[test]
+    fn test_case_and_trim() {
+        assert_eq!(run("upper", &[], "jeff"), "JEFF");
+        assert_eq!(run("lower", &[], "MAIN"), "main");
+        assert_eq!(run("trim", &[], "  x "), "x");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(run("truncate", &["5"], "feature/login"), "featu…");
+        assert_eq!(run("truncate", &["7", "..."], "feature/login"), "feature...");
+        assert_eq!(run("truncate", &["20", "…"], "main"), "main");
+        // Counted in columns, not bytes or characters
+        assert_eq!(run("truncate", &["2"], "äöü"), "äö…");
+        assert_eq!(run("truncate", &["5"], "日本語です"), "日本…");
+        assert_eq!(run("truncate", &["4", ""], "cafe\u{301}s"), "cafe\u{301}");
+    }
+
+    #[test]
+    fn test_replace_and_pad() {
+        assert_eq!(run("replace", &["prod-", ""], "prod-web-1"), "web-1");
+        assert_eq!(run("pad_left", &["8"], "9:05"), "    9:05");
+        assert_eq!(run("pad_right", &["4", "."], "ab"), "ab..");
+        assert_eq!(run("pad_left", &["2"], "long"), "long");
+        assert_eq!(run("pad_right", &["6"], "日本"), "日本  ");
+    }
+
+    #[test]
+    fn test_apply_all_chains_and_skips_invalid() {
+        let filters = [filter("basename", &[]), filter("bogus", &[]), filter("upper", &[])];
+        assert_eq!(apply_all(&filters, "/home/me/src"), "SRC");
+    }
+
+    #[test]
+    fn test_check_errors() {
+        assert!(check(&filter("bogus", &[])).unwrap_err().contains("Unknown filter: 'bogus'"));
+        assert!(check(&filter("upper", &["x"])).unwrap_err().contains("takes no arguments"));
+        assert!(check(&filter("truncate", &[])).unwrap_err().contains("1 to 2 arguments"));
+        assert!(check(&filter("truncate", &["ten"])).unwrap_err().contains("expected a number"));
+        assert!(check(&filter("replace", &["a"])).unwrap_err().contains("2 arguments"));
+        assert!(check(&filter("pad_left", &["4", "ab"])).unwrap_err().contains("single one-column character"));
+        assert!(check(&filter("pad_left", &["4", "日"])).is_err());
+        assert!(check(&filter("replace", &["", "x"])).is_err());
+        assert!(check(&filter("truncate", &["20", "…"])).is_ok());
+    }
+}
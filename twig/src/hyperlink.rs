@@ -0,0 +1,59 @@
+// twig/src/hyperlink.rs
+
+//! OSC 8 hyperlinks: `{cwd:link}`, `{git_branch:magenta,link}`
+//!
+//! Providers report a link target for some variables next to their values
+//! (the current directory as a `file://` URL, git variables as pages of the
+//! remote's web UI). The `link` style makes the renderer wrap the value in
+//! an OSC 8 sequence pointing there; terminals without OSC 8 support show
+//! the text unchanged.
+//!
+//! The sequences are terminated with BEL rather than `ESC \`, so no
+//! backslash ends up in a prompt string, and URLs are percent-encoded down
+//! to characters no shell treats specially (apart from `%`, which zsh and
+//! tcsh double). `hyperlinks = false` under `[prompt]` turns links off.
+
+/// Sequence that ends a hyperlink
+pub const CLOSE: &str = "\x1b]8;;\x07";
+
+/// Sequence that starts a hyperlink to `url`
+pub fn open(url: &str) -> String {
+    format!("\x1b]8;;{}\x07", url)
+}
+
+/// `file://` URL for an absolute path on `host`
+pub fn file_url(host: &str, path: &str) -> String {
+    format!("file://{}{}", encode(host), encode(path))
+}
+
+/// Percent-encode everything except unreserved URL characters and `/`
+pub fn encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("/home/me/src/twig"), "/home/me/src/twig");
+        assert_eq!(encode("/tmp/a b/$x`y\\"), "/tmp/a%20b/%24x%60y%5C");
+        assert_eq!(encode("/文档"), "/%E6%96%87%E6%A1%A3");
+    }
+
+    #[test]
+    fn test_file_url_and_sequences() {
+        assert_eq!(file_url("laptop", "/home/me/my project"), "file://laptop/home/me/my%20project");
+        assert_eq!(open("file://h/tmp"), "\x1b]8;;file://h/tmp\x07");
+        assert_eq!(CLOSE, "\x1b]8;;\x07");
+    }
+}
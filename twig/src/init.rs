@@ -0,0 +1,123 @@
+// twig/src/init.rs
+
+//! `twig init <shell>`: shell integration scripts
+//!
+//! ```sh
+//! eval "$(twig init bash)"      # ~/.bashrc
+//! eval "$(twig init zsh)"       # ~/.zshrc
+//! eval "`twig init tcsh`"       # ~/.tcshrc
+//! twig init fish | source       # ~/.config/fish/config.fish
+//! ```
+//!
+//! Each script redraws the prompt before every command line (rather than
+//! setting it once at startup) and passes the last command's state to twig
+//! in the environment: `TWIG_STATUS`, `TWIG_PIPESTATUS`, `TWIG_DURATION_MS`,
+//! `TWIG_JOBS` and `TWIG_KEYMAP`, where the shell has them. The terminal width is passed
+//! with `--width`, since twig's output is captured by the shell and its own
+//! detection may not see the terminal.
+//!
+//! With `--transient`, the bash and zsh scripts also redraw an accepted
+//! prompt in its `format_transient` form (see `shell/transient.*`).
+//!
+//! `tcsh` evaluates the script as a single line, so `init.tcsh` ends every
+//! statement with `;` and has no comments.
+
+/// Integration scripts, by shell name
+const SCRIPTS: [(&str, &str); 6] = [
+    ("bash", include_str!("../shell/init.bash")),
+    ("zsh", include_str!("../shell/init.zsh")),
+    ("tcsh", include_str!("../shell/init.tcsh")),
+    ("fish", include_str!("../shell/init.fish")),
+    ("pwsh", include_str!("../shell/init.ps1")),
+    ("nu", include_str!("../shell/init.nu")),
+];
+
+/// Transient prompt hooks appended by `--transient`, by shell name
+const TRANSIENT_SCRIPTS: [(&str, &str); 2] = [
+    ("bash", include_str!("../shell/transient.bash")),
+    ("zsh", include_str!("../shell/transient.zsh")),
+];
+
+/// Starts twigd in the background unless it is already running
+///
+/// Every shell can run an external `sh`, so one command line serves all of them.
+const DAEMON_COMMAND: &str = "sh -c 'pgrep -x twigd >/dev/null 2>&1 || twigd >/dev/null 2>&1 &'";
+
+/// Integration script for `shell`, optionally with transient prompt hooks
+/// and starting twigd
+pub fn script(shell: &str, transient: bool, daemon: bool) -> Result<String, String> {
+    let Some((_, script)) = SCRIPTS.iter().find(|(name, _)| *name == shell) else {
+        let names: Vec<&str> = SCRIPTS.iter().map(|(name, _)| *name).collect();
+        return Err(format!("Unknown shell: {}. Valid options: {}", shell, names.join(", ")));
+    };
+
+    let mut output = script.to_string();
+    if transient {
+        let Some((_, hooks)) = TRANSIENT_SCRIPTS.iter().find(|(name, _)| *name == shell) else {
+            let names: Vec<&str> = TRANSIENT_SCRIPTS.iter().map(|(name, _)| *name).collect();
+            return Err(format!("--transient is only supported for {}", names.join(" and ")));
+        };
+        output.push('\n');
+        output.push_str(hooks);
+    }
+    if daemon {
+        let line = match shell {
+            // External commands need a caret in nu
+            "nu" => format!("\n^{}\n", DAEMON_COMMAND),
+            "tcsh" => format!("{};\n", DAEMON_COMMAND),
+            _ => format!("\n# Start the twig daemon\n{}\n", DAEMON_COMMAND),
+        };
+        output.push_str(&line);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts_use_their_mode() {
+        for (shell, _) in SCRIPTS {
+            let output = script(shell, false, false).unwrap();
+            assert!(output.contains(&format!("twig --mode {} --width", shell)), "{}", shell);
+            assert!(!output.contains("twigd"), "{}", shell);
+        }
+    }
+
+    #[test]
+    fn test_tcsh_script_is_one_line_safe() {
+        // `eval "`twig init tcsh`"` joins the lines with spaces
+        let output = script("tcsh", false, true).unwrap();
+        assert!(output.lines().all(|line| line.ends_with(';')), "{}", output);
+        assert!(!output.contains('#'));
+    }
+
+    #[test]
+    fn test_daemon_autostart() {
+        assert!(script("bash", false, true).unwrap().ends_with(&format!("{}\n", DAEMON_COMMAND)));
+        assert!(script("nu", false, true).unwrap().contains(&format!("^{}", DAEMON_COMMAND)));
+    }
+
+    #[test]
+    fn test_transient_hooks() {
+        for shell in ["bash", "zsh"] {
+            assert!(!script(shell, false, false).unwrap().contains("--transient"), "{}", shell);
+            assert!(script(shell, true, false).unwrap().contains("--transient"), "{}", shell);
+        }
+        // zsh chains onto zle-line-finish rather than replacing it
+        let zsh = script("zsh", true, false).unwrap();
+        assert!(zsh.contains("add-zle-hook-widget line-finish _twig_transient"));
+        assert!(!zsh.contains("zle -N zle-line-finish"));
+
+        assert_eq!(script("fish", true, false).unwrap_err(), "--transient is only supported for bash and zsh");
+    }
+
+    #[test]
+    fn test_unknown_shell() {
+        assert_eq!(
+            script("csh", false, false).unwrap_err(),
+            "Unknown shell: csh. Valid options: bash, zsh, tcsh, fish, pwsh, nu"
+        );
+    }
+}
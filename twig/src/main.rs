@@ -1,18 +1,29 @@
 mod config;
+mod filter;
+mod hyperlink;
+mod init;
+mod powerline;
 mod providers;
+mod rules;
 mod shell;
+mod style;
+mod template;
+mod theme;
+mod width;
 
-use clap::Parser;
-use config::{Config, CwdConfig, HostnameConfig, PromptConfig, TimeConfig};
+use clap::{Parser, Subcommand};
+use config::{Config, CwdConfig, HostnameConfig, PromptConfig, SegmentConfig, TimeConfig};
 use directories::ProjectDirs;
 use regex::Regex;
-use shell::{get_formatter, ShellFormatter, ShellMode};
+use shell::{get_formatter, RawFormatter, ShellFormatter, ShellMode, UnwrappedFormatter};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use terminal_size::{terminal_size, terminal_size_using_fd, Width};
 use std::os::fd::AsRawFd;
+use style::{ColorDepth, Palette, Style};
+use template::{Fragments, Node, Placeholder, Source, Template};
 
 #[derive(Parser)]
 #[command(name = "twig")]
@@ -27,7 +38,7 @@ struct Cli {
     #[arg(long)]
     config: Option<PathBuf>,
 
-    /// Shell output mode (tcsh, bash, zsh) - outputs shell-specific prompt format
+    /// Shell output mode (tcsh, bash, zsh, fish, pwsh, nu) - outputs shell-specific prompt format
     #[arg(long, value_name = "SHELL")]
     mode: Option<String>,
 
@@ -38,39 +49,153 @@ struct Cli {
     /// Validate provider configurations and show any errors
     #[arg(long)]
     validate: bool,
+
+    /// Output the right prompt (format_right) instead of the left one (zsh RPROMPT, tcsh rprompt, fish_right_prompt, nu PROMPT_COMMAND_RIGHT)
+    #[arg(long)]
+    right: bool,
+
+    /// Render the compact format_transient that replaces a prompt once its command has run
+    #[arg(long)]
+    transient: bool,
+
+    /// Render a secondary prompt (continuation, trace, select) instead of the primary one
+    #[arg(long, value_name = "KIND")]
+    kind: Option<String>,
+
+    /// Terminal width in columns (default, or 0: detect from the terminal)
+    #[arg(long, value_name = "COLUMNS")]
+    width: Option<u16>,
+
+    /// Exit status of the last command, for {exit_code} and {exit_signal}
+    #[arg(long, value_name = "CODE", env = "TWIG_STATUS", allow_hyphen_values = true)]
+    status: Option<String>,
+
+    /// Exit status of each command in the last pipeline ("0 1 0"), for {exit_pipestatus}
+    #[arg(long, value_name = "CODES", env = "TWIG_PIPESTATUS", allow_hyphen_values = true)]
+    pipestatus: Option<String>,
+
+    /// How long the last command ran in milliseconds, for {cmd_duration}
+    #[arg(long, value_name = "MS", env = "TWIG_DURATION_MS")]
+    duration_ms: Option<String>,
+
+    /// Number of background jobs, for {jobs}
+    #[arg(long, value_name = "COUNT", env = "TWIG_JOBS")]
+    jobs: Option<String>,
+
+    /// Line editor keymap (vicmd, viins, ...), for {vi_mode}
+    #[arg(long, value_name = "KEYMAP", env = "TWIG_KEYMAP")]
+    keymap: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the shell integration script, e.g. `eval "$(twig init bash)"`
+    Init {
+        /// Shell to integrate with (bash, zsh, tcsh, fish, pwsh, nu)
+        shell: String,
+
+        /// Also redraw accepted prompts with format_transient (bash, zsh)
+        #[arg(long)]
+        transient: bool,
+
+        /// Also start twigd in the background if it isn't running
+        #[arg(long)]
+        daemon: bool,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    if let Some(Command::Init { shell, transient, daemon }) = &cli.command {
+        match init::script(shell, *transient, *daemon) {
+            Ok(script) => print!("{}", script),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     let start = Instant::now();
 
+    // The last command's state, as passed by the shell
+    providers::status::set_last_command(providers::status::LastCommand::parse(
+        cli.status.as_deref(),
+        cli.pipestatus.as_deref(),
+        cli.duration_ms.as_deref(),
+        cli.jobs.as_deref(),
+        cli.keymap.as_deref(),
+    ));
+
     // Load config from file (or create default)
     let config_start = Instant::now();
     let (mut config, config_path) = load_config(cli.config.as_deref());
+    width::set_ambiguous_width(config.prompt.ambiguous_width.unwrap_or_default());
 
     // Detect terminal width for responsive prompt selection
     // Try stdout first, then stderr (useful when stdout is captured by shell for prompts)
-    let terminal_width = terminal_size()
-        .or_else(|| {
-            // If stdout doesn't work, try stderr
-            terminal_size_using_fd(std::io::stderr().as_raw_fd())
-        })
-        .map(|(Width(w), _)| w);
+    // (unless the shell passed it with --width)
+    let terminal_width = cli.width.filter(|&w| w > 0).or_else(|| {
+        terminal_size()
+            .or_else(|| {
+                // If stdout doesn't work, try stderr
+                terminal_size_using_fd(std::io::stderr().as_raw_fd())
+            })
+            .map(|(Width(w), _)| w)
+    });
+
+    // Secondary and transient prompts are a single compact format:
+    // no segments, right prompt or responsive switching
+    let compact_format = match compact_format(&config.prompt, cli.kind.as_deref(), cli.transient) {
+        Ok(format) => format.map(str::to_string),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let compact = compact_format.is_some();
 
-    // Get the appropriate prompt format based on terminal width
-    let format = config.prompt.get_format(terminal_width).to_string();
+    // Formats to try based on terminal width, most detailed first
+    let formats: Vec<String> = match compact_format {
+        Some(format) => vec![format],
+        None => config.prompt.candidate_formats(terminal_width).into_iter().map(str::to_string).collect(),
+    };
 
     let is_debug = cli.debug || std::env::var("TWIG_DEBUG").is_ok();
     if is_debug {
-        eprintln!("[DEBUG] Initial format selected: {}", if format.len() > 50 { &format[..50] } else { &format });
+        eprintln!("[DEBUG] Initial format selected: {}", width::truncate(&formats[0], 50));
         eprintln!("[DEBUG] Terminal width: {:?}", terminal_width);
         eprintln!("[DEBUG] width_threshold: {:?}", config.prompt.width_threshold);
-        eprintln!("[DEBUG] Has format_narrow: {}", config.prompt.format_narrow.is_some());
+        eprintln!("[DEBUG] Candidate formats: {}", formats.len());
+        eprintln!("[DEBUG] Has format_right: {}", config.prompt.format_right.is_some());
+        eprintln!("[DEBUG] Compact (secondary or transient): {}", compact);
     }
 
-    // Apply implicit sections for variables used in template
-    apply_implicit_sections(&mut config, &format);
+    // Parse the format strings once; every later step works on the AST
+    let templates: Vec<Template> = formats.iter()
+        .map(|format| parse_template(format, &config.fragments))
+        .collect();
+
+    // Powerline segments are templates of their own
+    let segments: Vec<Template> = config.prompt.segments.iter()
+        .filter(|_| !compact)
+        .map(|segment| parse_template(&segment.format, &config.fragments))
+        .collect();
+
+    // The right prompt, if configured
+    let right_template = config.prompt.format_right.as_deref()
+        .filter(|_| !compact)
+        .map(|format| parse_template(format, &config.fragments));
+
+    // Apply implicit sections for variables used in any template
+    for template in templates.iter().chain(&segments).chain(&right_template) {
+        apply_implicit_sections(&mut config, template);
+    }
 
     let config_time = config_start.elapsed();
 
@@ -82,17 +207,23 @@ fn main() {
     }
 
     // Extract variables from template to determine which providers to run
-    let template_vars = extract_all_variables(&format);
-    let template_var_refs: Vec<&str> = template_vars.iter().map(|s| s.as_str()).collect();
-    let needed_providers = registry.determine_providers(&template_var_refs);
+    let used_variables: Vec<&str> = templates.iter()
+        .chain(&segments)
+        .chain(&right_template)
+        .flat_map(|t| t.variables())
+        .collect();
+    let needed_providers = registry.determine_providers(&used_variables);
 
     // Collect variables only from needed providers (performance optimization)
     let render_start = Instant::now();
-    let (mut variables, provider_timings) = match registry.collect_from(&needed_providers, &config, false) {
-        Ok(result) => (result.variables, result.timings),
-        Err(_) => (HashMap::new(), Vec::new()), // Should not happen - providers catch errors in non-validate mode
+    let (mut variables, numbers, links, provider_timings) = match registry.collect_from(&needed_providers, &config, false) {
+        Ok(result) => (result.variables, result.numbers, result.links, result.timings),
+        Err(_) => (HashMap::new(), HashMap::new(), HashMap::new(), Vec::new()), // Should not happen - providers catch errors in non-validate mode
     };
 
+    // Threshold rules hide variables or add styles based on raw provider numbers
+    let rule_styles = rules::apply_rules(&config, &mut variables, &numbers);
+
     // Add terminal width as a built-in variable
     // This is always available, showing either the detected width or "N/A"
     let width_str = terminal_width
@@ -107,8 +238,11 @@ fn main() {
             "tcsh" => ShellMode::Tcsh,
             "bash" => ShellMode::Bash,
             "zsh" => ShellMode::Zsh,
+            "fish" => ShellMode::Fish,
+            "pwsh" => ShellMode::Pwsh,
+            "nu" => ShellMode::Nu,
             other => {
-                eprintln!("Unknown shell mode: {}. Valid options: tcsh, bash, zsh", other);
+                eprintln!("Unknown shell mode: {}. Valid options: tcsh, bash, zsh, fish, pwsh, nu", other);
                 std::process::exit(1);
             }
         };
@@ -122,46 +256,97 @@ fn main() {
     };
 
     // Create formatter for the selected shell mode
+    // Bash prints PS3 (the select prompt) as-is, without decoding \[...\]
+    let shell_mode = match (shell_mode, cli.kind.as_deref()) {
+        (ShellMode::Bash, Some("select")) => ShellMode::Raw,
+        _ => shell_mode,
+    };
     let formatter = get_formatter(shell_mode);
 
-    // Perform variable substitution with color support
-    let mut output = substitute_variables(&format, &variables, formatter.as_ref());
-
-    // Post-process output for shell-specific requirements (e.g., escape newlines for TCSH/Zsh)
-    output = formatter.finalize(&output);
-
-    // Dynamic length-based switching (if no width_threshold is set)
-    let mut format_used = format.clone();
-    if config.prompt.width_threshold.is_none() {
-        if let (Some(width), Some(ref narrow_format)) = (terminal_width, &config.prompt.format_narrow) {
-            // Measure visible length (strip ANSI codes and shell wrappers)
-            let visible_len = visible_length(&output);
-            let padding = config.prompt.padding.unwrap_or(5);
-
-            if is_debug {
-                eprintln!("[DEBUG] Rendered prompt visible length: {}", visible_len);
-                eprintln!("[DEBUG] Terminal width: {}", width);
-                eprintln!("[DEBUG] Padding: {}", padding);
-                eprintln!("[DEBUG] Check: {} + {} > {}? {}", visible_len, padding, width,
-                          visible_len as i32 + padding > width as i32);
-            }
+    // Colors are downsampled to what the terminal supports
+    let ctx = RenderContext {
+        variables: &variables,
+        config: &config,
+        rule_styles: &rule_styles,
+        base: &[],
+        formatter: formatter.as_ref(),
+        color_depth: config.prompt.color_depth.unwrap_or_else(ColorDepth::detect),
+        links: &links,
+    };
 
-            // If prompt is too long, switch to narrow format
-            // Support negative padding to allow overflow before switching
-            if visible_len as i32 + padding > width as i32 {
+    // Perform variable substitution with color support
+    let mut output = render_prompt(&segments, &templates[0], &ctx);
+
+    // Right prompt: zsh and tcsh draw it themselves; for bash and raw output it is
+    // positioned with cursor movement, so it is rendered with unwrapped codes
+    // (values are still escaped for the shell)
+    let native_right = formatter.native_right_prompt();
+    let unwrapped = UnwrappedFormatter(formatter.as_ref());
+    let right_ctx = RenderContext { formatter: &unwrapped, ..ctx };
+    let mut right = right_template
+        .as_ref()
+        .map(|t| render_template(t, if native_right { &ctx } else { &right_ctx }))
+        // A right prompt has no room to fill
+        .map(|r| resolve_fills(&r, None, 0, &RawFormatter))
+        .filter(|r| !r.is_empty());
+
+    // Drop the right prompt before the left one gets narrowed
+    if let Some(ref r) = right {
+        match terminal_width {
+            Some(width) => {
+                let fits = right_prompt_fits(&output, r, width, config.prompt.padding.unwrap_or(5), formatter.as_ref());
                 if is_debug {
-                    eprintln!("[DEBUG] Switching to narrow format!");
+                    eprintln!("[DEBUG] Right prompt fits: {}", fits);
                 }
-                // Re-render with narrow format
-                output = substitute_variables(narrow_format, &variables, formatter.as_ref());
-                output = formatter.finalize(&output);
-                format_used = narrow_format.clone();
-            } else {
-                if is_debug {
-                    eprintln!("[DEBUG] Keeping original format (fits in terminal)");
+                if !fits {
+                    right = None;
                 }
             }
+            // Can't right-align without knowing the width
+            None if !native_right => right = None,
+            None => {}
+        }
+    }
+
+    // Dynamic length-based switching: drop low-priority parts, then move on
+    // to the next format, until the prompt fits
+    let mut format_used = formats[0].clone();
+    if let Some(width) = terminal_width {
+        let padding = config.prompt.padding.unwrap_or(5);
+        if is_debug {
+            eprintln!("[DEBUG] Rendered prompt visible length: {}", visible_length(&output, formatter.as_ref()));
+            eprintln!("[DEBUG] Terminal width: {}", width);
+            eprintln!("[DEBUG] Padding: {}", padding);
         }
+
+        let (index, fitting) = render_first_fitting(&segments, &templates, &ctx, width, padding);
+        if is_debug {
+            eprintln!("[DEBUG] Using format {} of {}", index + 1, templates.len());
+        }
+        output = fitting;
+        format_used = formats[index].clone();
+    }
+
+    // Pad {fill}s to the terminal width, leaving room for the right prompt
+    let reserved = right.as_ref().map_or(0, |r| visible_length(r, formatter.as_ref()) + 1);
+    output = resolve_fills(&output, terminal_width, reserved, formatter.as_ref());
+
+    // Place the right prompt on the left prompt's last line (bash and raw)
+    if let (Some(r), Some(width), false) = (&right, terminal_width, native_right) {
+        output = if show_box {
+            // The box is not interactive: pad with spaces instead of moving the cursor
+            let gap = (width as usize).saturating_sub(last_line_length(&output, formatter.as_ref()) + visible_length(r, formatter.as_ref()));
+            format!("{}{}{}", output, " ".repeat(gap), r)
+        } else {
+            let column = (width as usize + 1).saturating_sub(visible_length(r, formatter.as_ref()));
+            insert_at_last_line(&output, &formatter.position_right(r, column))
+        };
+    }
+
+    // Post-process output for shell-specific requirements (e.g., escape newlines for TCSH/Zsh)
+    output = formatter.finalize(&output);
+    if cli.right {
+        output = right.map(|r| formatter.finalize(&r)).unwrap_or_default();
     }
 
     let render_time = render_start.elapsed();
@@ -194,7 +379,7 @@ fn main() {
 /// Print the prompt in a box with timing information
 fn print_boxed(
     prompt: &str,
-    config_path: &PathBuf,
+    config_path: &Path,
     terminal_width: Option<u16>,
     config_time: std::time::Duration,
     render_time: std::time::Duration,
@@ -212,15 +397,15 @@ fn print_boxed(
     let lines: Vec<&str> = prompt.split('\n').collect();
     let text_lines: Vec<String> = lines.iter().map(|line| strip_ansi_codes(line)).collect();
 
-    // Find the maximum width across all lines (using character count, not byte length)
-    let max_width = text_lines.iter().map(|line| line.chars().count()).max().unwrap_or(0).max(50);
+    // Find the maximum width across all lines (in terminal columns, not bytes)
+    let max_width = text_lines.iter().map(|line| width::display_width(line)).max().unwrap_or(0).max(50);
 
     // Top border
     println!("┌{}┐", "─".repeat(max_width + 2));
 
     // Print each line with proper padding
     for (i, line) in lines.iter().enumerate() {
-        let text_len = text_lines[i].chars().count();
+        let text_len = width::display_width(&text_lines[i]);
         let padding = " ".repeat(max_width - text_len);
         println!("│ {}{} │", line, padding);
     }
@@ -248,7 +433,7 @@ fn print_boxed(
 
 /// Print debug information in a classy box to stderr
 fn print_debug_box(
-    config_path: &PathBuf,
+    config_path: &Path,
     terminal_width: Option<u16>,
     format_used: &str,
     config_time: std::time::Duration,
@@ -261,8 +446,8 @@ fn print_debug_box(
         .unwrap_or_default();
     let config_str = format!("📄 Config: {}{}", config_path.display(), width_str);
 
-    let format_preview = if format_used.len() > 50 {
-        format!("{}...", &format_used[..47])
+    let format_preview = if width::display_width(format_used) > 50 {
+        format!("{}...", width::truncate(format_used, 47))
     } else {
         format_used.to_string()
     };
@@ -281,14 +466,8 @@ fn print_debug_box(
         .map(|t| format!("   {}: {:.2}ms", t.name, t.duration.as_secs_f64() * 1000.0))
         .collect();
 
-    // Calculate display width (accounting for emoji being 2 chars wide)
-    // Each line has 1 emoji (2 char width) but counts as more bytes
-    let display_width = |s: &str| {
-        // Count chars but emojis display as 2 wide
-        let char_count = s.chars().count();
-        let emoji_count = s.chars().filter(|c| *c as u32 > 0x1F000).count();
-        char_count + emoji_count // Add extra width for emojis
-    };
+    // Emoji take two columns
+    let display_width = width::display_width;
 
     let config_width = display_width(&config_str);
     let format_width = display_width(&format_str);
@@ -324,8 +503,9 @@ fn print_debug_box(
 }
 
 /// Strip ANSI escape codes from a string to get actual text length
+/// (SGR color codes and OSC 8 hyperlink sequences)
 fn strip_ansi_codes(s: &str) -> String {
-    let re = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    let re = Regex::new(r"\x1b\[[0-9;]*m|\x1b\]8;[^\x07]*\x07").unwrap();
     re.replace_all(s, "").to_string()
 }
 
@@ -335,23 +515,32 @@ fn strip_ansi_codes(s: &str) -> String {
 /// Create a minimal fallback config when parsing fails
 fn create_fallback_config() -> Config {
     Config {
+        theme: None,
+        styles: Palette::new(),
+        fragments: Fragments::new(),
         time: None,
-        hostname: Some(HostnameConfig { name: None }),
-        cwd: Some(CwdConfig { name: None }),
+        hostname: Some(HostnameConfig::default()),
+        cwd: Some(CwdConfig::default()),
         git: None,
         ip: None,
         battery: None,
+        exit: None,
+        cmd: None,
+        jobs: None,
+        vi: None,
         prompt: PromptConfig {
             format: "{$USER}@{hostname}:{cwd}$ ".to_string(),
             format_wide: None,
             format_narrow: None,
             width_threshold: None, // Use dynamic length-based switching
             padding: None, // Default: 5 characters
+            color_depth: None, // Detect from COLORTERM/TERM
+            ..Default::default()
         },
     }
 }
 
-fn load_config(custom_path: Option<&std::path::Path>) -> (Config, PathBuf) {
+fn load_config(custom_path: Option<&Path>) -> (Config, PathBuf) {
     let config_path = custom_path
         .map(|p| p.to_path_buf())
         .unwrap_or_else(get_config_path);
@@ -361,7 +550,10 @@ fn load_config(custom_path: Option<&std::path::Path>) -> (Config, PathBuf) {
         match fs::read_to_string(&config_path) {
             Ok(contents) => {
                 match toml::from_str::<Config>(&contents) {
-                    Ok(config) => config,
+                    Ok(mut config) => {
+                        load_theme(&mut config, &config_path);
+                        config
+                    }
                     Err(e) => {
                         // Config parse error - show error and use fallback
                         eprintln!("\x1b[31mError:\x1b[0m Failed to parse config file: {}", config_path.display());
@@ -407,10 +599,42 @@ fn load_config(custom_path: Option<&std::path::Path>) -> (Config, PathBuf) {
     (config, config_path)
 }
 
+/// Format for a secondary prompt kind, with the shell's usual default
+///
+/// - `continuation`: unfinished command lines (PS2, tcsh prompt2)
+/// - `trace`: `set -x` / `setopt xtrace` output (PS4)
+/// - `select`: the `select` builtin's menu (PS3)
+fn secondary_format<'a>(prompt: &'a PromptConfig, kind: &str) -> Result<&'a str, String> {
+    let (format, default) = match kind {
+        "continuation" => (&prompt.continuation, "> "),
+        "trace" => (&prompt.trace, "+ "),
+        "select" => (&prompt.select, "#? "),
+        other => {
+            return Err(format!(
+                "Unknown prompt kind: {}. Valid options: continuation, trace, select",
+                other
+            ))
+        }
+    };
+    Ok(format.as_deref().unwrap_or(default))
+}
+
+/// The single compact format for a secondary (`--kind`) or `--transient` prompt
+///
+/// `None` means the full prompt is rendered, as it is for `--transient`
+/// without a `format_transient`.
+fn compact_format<'a>(prompt: &'a PromptConfig, kind: Option<&str>, transient: bool) -> Result<Option<&'a str>, String> {
+    match kind {
+        Some(kind) => secondary_format(prompt, kind).map(Some),
+        None if transient => Ok(prompt.format_transient.as_deref()),
+        None => Ok(None),
+    }
+}
+
 /// Validate configuration with three levels of checks
 fn validate_config(
     config: &Config,
-    config_path: &PathBuf,
+    config_path: &Path,
     registry: &providers::ProviderRegistry,
 ) -> bool {
     let mut success = true;
@@ -420,7 +644,8 @@ fn validate_config(
 
     // Validate all format strings (default, wide, narrow)
     let format = &config.prompt.format;
-    match validate_format_syntax(format) {
+    let format_valid = validate_format_syntax(format, &config.fragments);
+    match &format_valid {
         Ok(vars) => {
             println!("{} Config file found ({})", ok, config_path.display());
             println!("{} TOML syntax valid", ok);
@@ -432,9 +657,22 @@ fn validate_config(
         }
     }
 
+    // Validate fragments (cycles included, even in unused fragments)
+    match validate_fragments(config) {
+        Ok(count) => {
+            if count > 0 {
+                println!("{} Fragments valid ({} found)", ok, count);
+            }
+        }
+        Err(e) => {
+            println!("❌ {}", e);
+            success = false;
+        }
+    }
+
     // Validate format_wide if configured
     if let Some(ref format_wide) = config.prompt.format_wide {
-        match validate_format_syntax(format_wide) {
+        match validate_format_syntax(format_wide, &config.fragments) {
             Ok(vars) => {
                 println!("{} Format wide valid ({} variables)", ok, vars.len());
             }
@@ -447,7 +685,7 @@ fn validate_config(
 
     // Validate format_narrow if configured
     if let Some(ref format_narrow) = config.prompt.format_narrow {
-        match validate_format_syntax(format_narrow) {
+        match validate_format_syntax(format_narrow, &config.fragments) {
             Ok(vars) => {
                 println!("{} Format narrow valid ({} variables)", ok, vars.len());
             }
@@ -458,11 +696,120 @@ fn validate_config(
         }
     }
 
-    // Validate colors and styles
-    match validate_colors_and_styles(format) {
+    // Validate the optional formats if configured
+    let extra_formats = [
+        ("Format right", &config.prompt.format_right),
+        ("Format transient", &config.prompt.format_transient),
+        ("Continuation prompt", &config.prompt.continuation),
+        ("Trace prompt", &config.prompt.trace),
+        ("Select prompt", &config.prompt.select),
+    ];
+    for (label, format) in extra_formats {
+        let Some(format) = format else {
+            continue;
+        };
+        let result = validate_format_syntax(format, &config.fragments).and_then(|vars| {
+            validate_colors_and_styles(format, &config.styles, &config.fragments)?;
+            validate_filters(format, &config.fragments)?;
+            Ok(vars)
+        });
+        match result {
+            Ok(vars) => {
+                println!("{} {} valid ({} variables)", ok, label, vars.len());
+            }
+            Err(e) => {
+                println!("❌ {}: {}", label, e);
+                success = false;
+            }
+        }
+    }
+
+    // Validate responsive formats
+    match validate_formats(config) {
+        Ok(count) => {
+            if count > 0 {
+                println!("{} Responsive formats valid ({} found)", ok, count);
+            }
+        }
+        Err(e) => {
+            println!("❌ {}", e);
+            success = false;
+        }
+    }
+
+    // Validate powerline segments
+    let prompt = &config.prompt;
+    if prompt.format.is_empty() && prompt.formats.is_empty() && prompt.segments.is_empty() {
+        println!("❌ Prompt has no format, [[prompt.formats]] or [[prompt.segments]]");
+        success = false;
+    }
+    match validate_segments(config) {
+        Ok(count) => {
+            if count > 0 {
+                println!("{} Segments valid ({} found)", ok, count);
+            }
+        }
+        Err(e) => {
+            println!("❌ {}", e);
+            success = false;
+        }
+    }
+
+    // Validate theme and named styles
+    if let Some(name) = &config.theme {
+        match theme::load(name, config_path.parent().unwrap_or(Path::new("."))) {
+            Ok(palette) => println!("{} Theme '{}' loaded ({} styles)", ok, name, palette.len()),
+            Err(e) => {
+                println!("❌ {}", e);
+                success = false;
+            }
+        }
+    }
+    match validate_palette(&config.styles) {
+        Ok(count) => {
+            if count > 0 {
+                println!("{} Named styles valid ({} found)", ok, count);
+            }
+        }
+        Err(e) => {
+            println!("❌ {}", e);
+            success = false;
+        }
+    }
+
+    // Validate colors and styles (syntax errors were already reported above)
+    if format_valid.is_ok() {
+        match validate_colors_and_styles(format, &config.styles, &config.fragments) {
+            Ok(count) => {
+                if count > 0 {
+                    println!("{} Colors and styles valid ({} found)", ok, count);
+                }
+            }
+            Err(e) => {
+                println!("❌ {}", e);
+                success = false;
+            }
+        }
+    }
+
+    // Validate value-mapped styles in section `styles` tables
+    match validate_value_styles(config) {
+        Ok(count) => {
+            if count > 0 {
+                println!("{} Value styles valid ({} mappings)", ok, count);
+            }
+        }
+        Err(e) => {
+            println!("❌ {}", e);
+            success = false;
+        }
+    }
+
+    // Validate section rules
+    match validate_rules(config) {
         Ok(count) => {
             if count > 0 {
-                println!("{} Colors and styles valid ({} found)", ok, count);
+                println!("{} Rules valid ({} found)", ok, count);
             }
         }
         Err(e) => {
@@ -471,6 +818,21 @@ fn validate_config(
         }
     }
 
+    // Validate filters (syntax errors were already reported above)
+    if format_valid.is_ok() {
+        match validate_filters(format, &config.fragments) {
+            Ok(count) => {
+                if count > 0 {
+                    println!("{} Filters valid ({} found)", ok, count);
+                }
+            }
+            Err(e) => {
+                println!("❌ {}", e);
+                success = false;
+            }
+        }
+    }
+
     // Validate time format
     if let Some(time_config) = &config.time {
         if validate_time_format(&time_config.format) {
@@ -508,18 +870,18 @@ fn validate_config(
     // Test prompt rendering
     if provider_success {
         if let Ok(result) = provider_result {
-            let test_render = render_prompt(format, &result.variables);
+            let test_render = strip_ansi_codes(&render_validation_prompt(config, result));
             if !test_render.is_empty() {
                 println!("{} Prompt renders successfully", ok);
 
                 // Check prompt length
-                let visual_length = test_render.chars().count();
+                let visual_length = visible_length(&test_render, &RawFormatter);
                 if visual_length > 200 {
-                    warnings.push(format!("Prompt is long ({} chars), may wrap on narrow terminals", visual_length));
+                    warnings.push(format!("Prompt is long ({} columns), may wrap on narrow terminals", visual_length));
                 }
 
                 // Shell compatibility
-                println!("{} Shell compatibility verified (Raw, Tcsh, Bash, Zsh)", ok);
+                println!("{} Shell compatibility verified (Raw, Tcsh, Bash, Zsh, Fish, Pwsh, Nu)", ok);
             } else {
                 warnings.push("Prompt rendering produced empty output".to_string());
             }
@@ -545,48 +907,183 @@ fn validate_config(
     success
 }
 
+/// Render the prompt as configured (fragments, palette, rules, segments) for --validate
+fn render_validation_prompt(config: &Config, result: providers::CollectResult) -> String {
+    // `[[prompt.formats]]` take the place of `format`
+    let format = config.prompt.formats.first().map_or(&config.prompt.format, |entry| &entry.format);
+    let template = parse_template(format, &config.fragments);
+    let segments: Vec<Template> = config.prompt.segments.iter()
+        .map(|segment| parse_template(&segment.format, &config.fragments))
+        .collect();
+
+    let mut variables = result.variables;
+    let rule_styles = rules::apply_rules(config, &mut variables, &result.numbers);
+    let ctx = RenderContext {
+        variables: &variables,
+        config,
+        rule_styles: &rule_styles,
+        base: &[],
+        formatter: &RawFormatter,
+        color_depth: ColorDepth::TrueColor,
+        links: &result.links,
+    };
+    resolve_fills(&render_prompt(&segments, &template, &ctx), None, 0, &RawFormatter)
+}
+
 /// Validate format string syntax
-fn validate_format_syntax(format: &str) -> Result<Vec<String>, String> {
-    let mut variables = Vec::new();
-    let var_regex = Regex::new(r"\{([^}:]+)(?::([^}]+))?\}").unwrap();
+/// Returns the provider variables used by the format string
+fn validate_format_syntax(format: &str, fragments: &Fragments) -> Result<Vec<String>, String> {
+    let template = Template::parse_with(format, fragments).map_err(|e| e.to_string())?;
+    Ok(template.variables().into_iter().map(String::from).collect())
+}
+
+/// Validate colors and styles in format string
+/// Uses the same palette and style table as the renderer, so anything accepted here renders
+fn validate_colors_and_styles(format: &str, palette: &Palette, fragments: &Fragments) -> Result<usize, String> {
+    let template = Template::parse_with(format, fragments).map_err(|e| e.to_string())?;
+    let mut count = 0;
+
+    for style in template.styles() {
+        parse_style_spec(&style.parts, palette)?;
+        count += style.parts.len();
+    }
+
+    Ok(count)
+}
+
+/// Parse style parts after expanding palette names
+fn parse_style_spec<S: AsRef<str>>(parts: &[S], palette: &Palette) -> Result<Style, String> {
+    Style::parse(&style::resolve(parts, palette)?)
+}
+
+/// Validate the `[styles]` palette (theme entries included)
+/// Returns the number of named styles
+fn validate_palette(palette: &Palette) -> Result<usize, String> {
+    // Sorted so the first reported error is stable
+    let mut entries: Vec<_> = palette.iter().collect();
+    entries.sort();
+    for (name, spec) in &entries {
+        parse_style_spec(&style::split_spec(spec), palette)
+            .map_err(|e| format!("[styles] {}: {}", name, e))?;
+    }
+    Ok(entries.len())
+}
 
-    for cap in var_regex.captures_iter(format) {
-        let var_name = cap.get(1).unwrap().as_str();
+/// Validate `[fragments]`: names, syntax, references (unknown or cyclic), styles and filters
+/// Returns the number of fragments
+fn validate_fragments(config: &Config) -> Result<usize, String> {
+    // Sorted so the first reported error is stable
+    let mut fragments: Vec<_> = config.fragments.iter().collect();
+    fragments.sort();
+
+    for (name, text) in &fragments {
+        let context = |e: String| format!("[fragments] {}: {}", name, e);
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+            return Err(context("fragment names may only contain letters, digits, '_' and '-'".to_string()));
+        }
+        Template::parse_fragment(name, &config.fragments).map_err(|e| context(e.to_string()))?;
+        validate_colors_and_styles(text, &config.styles, &config.fragments).map_err(context)?;
+        validate_filters(text, &config.fragments).map_err(context)?;
+    }
+    Ok(fragments.len())
+}
 
-        // Check for invalid variable names
-        if var_name.is_empty() {
-            return Err("Empty variable name found".to_string());
+/// Validate `[[prompt.segments]]`: format syntax, inner styles and filters, fg/bg
+/// Returns the number of segments
+fn validate_segments(config: &Config) -> Result<usize, String> {
+    for (i, segment) in config.prompt.segments.iter().enumerate() {
+        let context = |e: String| format!("[[prompt.segments]] #{}: {}", i + 1, e);
+
+        validate_colors_and_styles(&segment.format, &config.styles, &config.fragments).map_err(context)?;
+        validate_filters(&segment.format, &config.fragments).map_err(context)?;
+        if let Some(fg) = &segment.fg {
+            parse_style_spec(&style::split_spec(fg), &config.styles)
+                .map_err(|e| context(format!("fg: {}", e)))?;
         }
+        if let Some(bg) = &segment.bg {
+            parse_style_spec(&[format!("bg:{}", bg.trim())], &config.styles)
+                .map_err(|e| context(format!("bg: {}", e)))?;
+        }
+    }
+    Ok(config.prompt.segments.len())
+}
 
-        // Skip literal text (starts with ")
-        if !var_name.starts_with('"') && !var_name.starts_with('$') {
-            variables.push(var_name.to_string());
+/// Validate `[[prompt.formats]]` entries
+/// Returns the number of formats
+fn validate_formats(config: &Config) -> Result<usize, String> {
+    for (i, entry) in config.prompt.formats.iter().enumerate() {
+        let context = |e: String| format!("[[prompt.formats]] #{}: {}", i + 1, e);
+
+        validate_format_syntax(&entry.format, &config.fragments).map_err(context)?;
+        validate_colors_and_styles(&entry.format, &config.styles, &config.fragments).map_err(context)?;
+        validate_filters(&entry.format, &config.fragments).map_err(context)?;
+        if let (Some(min), Some(max)) = (entry.min_width, entry.max_width) {
+            if min > max {
+                return Err(context(format!("min_width ({}) is greater than max_width ({})", min, max)));
+            }
         }
     }
+    Ok(config.prompt.formats.len())
+}
+
+/// Validate filter names and arguments in format string
+/// Returns the number of filters
+fn validate_filters(format: &str, fragments: &Fragments) -> Result<usize, String> {
+    let template = Template::parse_with(format, fragments).map_err(|e| e.to_string())?;
+    let mut count = 0;
+
+    for f in template.filters() {
+        filter::check(f).map_err(|e| {
+            let position = template::TemplateError::new("", f.span, format);
+            format!("{} at line {}, column {}", e, position.line, position.column)
+        })?;
+        count += 1;
+    }
 
-    Ok(variables)
+    Ok(count)
 }
 
-/// Validate colors and styles in format string
-fn validate_colors_and_styles(format: &str) -> Result<usize, String> {
-    let valid_colors = vec![
-        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
-        "bright_black", "bright_red", "bright_green", "bright_yellow",
-        "bright_blue", "bright_magenta", "bright_cyan", "bright_white",
-    ];
-    let valid_styles = vec!["bold", "italic", "underline", "dim"];
+/// Validate the `styles` tables of all configured sections
+/// Returns the number of mappings
+fn validate_value_styles(config: &Config) -> Result<usize, String> {
+    let mut count = 0;
+
+    for section in config::SECTIONS {
+        let Some(styles) = config.section_styles(section) else {
+            continue;
+        };
+
+        // Sorted so the first reported error is stable
+        let mut entries: Vec<_> = styles.iter().collect();
+        entries.sort();
+        for (value, spec) in entries {
+            parse_style_spec(&style::split_spec(spec), &config.styles)
+                .map_err(|e| format!("[{}] styles.\"{}\": {}", section, value, e))?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
 
-    let style_regex = Regex::new(r"\{[^}]+:([^}]+)\}").unwrap();
+/// Validate the `rules` of all configured sections
+/// Returns the number of rules
+fn validate_rules(config: &Config) -> Result<usize, String> {
     let mut count = 0;
 
-    for cap in style_regex.captures_iter(format) {
-        let style_spec = cap.get(1).unwrap().as_str();
-        let parts: Vec<&str> = style_spec.split(',').collect();
+    for section in config::SECTIONS {
+        for (index, rule) in config.section_rules(section).unwrap_or_default().iter().enumerate() {
+            let context = format!("[[{}.rules]] #{}", section, index + 1);
+            rules::Condition::parse(&rule.when).map_err(|e| format!("{}: {}", context, e))?;
 
-        for part in parts {
-            let part = part.trim();
-            if !valid_colors.contains(&part) && !valid_styles.contains(&part) {
-                return Err(format!("Unknown color or style: '{}'", part));
+            match &rule.style {
+                Some(spec) => {
+                    parse_style_spec(&style::split_spec(spec), &config.styles).map_err(|e| format!("{}: {}", context, e))?;
+                }
+                None if !rule.hide => {
+                    return Err(format!("{}: rule needs a style or hide = true", context));
+                }
+                None => {}
             }
             count += 1;
         }
@@ -616,48 +1113,24 @@ fn validate_time_format(format: &str) -> bool {
     true
 }
 
-/// Render prompt for testing (simplified version without shell formatting)
-fn render_prompt(template: &str, variables: &HashMap<String, String>) -> String {
-    let mut result = template.to_string();
-
-    // Replace variables
-    let var_regex = Regex::new(r"\{([^}:]+)(?::([^}]+))?\}").unwrap();
-
-    for cap in var_regex.captures_iter(template) {
-        let full_match = cap.get(0).unwrap().as_str();
-        let var_name = cap.get(1).unwrap().as_str();
-
-        // Handle literal text
-        if var_name.starts_with('"') && var_name.ends_with('"') {
-            let literal = &var_name[1..var_name.len() - 1];
-            result = result.replace(full_match, literal);
-            continue;
-        }
-
-        // Handle environment variables
-        if let Some(env_name) = var_name.strip_prefix('$') {
-            if let Ok(value) = std::env::var(env_name) {
-                result = result.replace(full_match, &value);
-            } else {
-                result = result.replace(full_match, "");
-            }
-            continue;
-        }
+/// Get config file path: ~/.config/twig/config.toml
+/// Merge the configured theme's palette into `config.styles`
+/// On failure the error is shown and the config's own styles are used
+fn load_theme(config: &mut Config, config_path: &Path) {
+    let Some(name) = config.theme.clone() else {
+        return;
+    };
+    let config_dir = config_path.parent().unwrap_or(Path::new("."));
 
-        // Handle regular variables
-        if let Some(value) = variables.get(var_name) {
-            result = result.replace(full_match, value);
-        } else {
-            result = result.replace(full_match, "");
+    match theme::load(&name, config_dir) {
+        Ok(palette) => theme::merge(&mut config.styles, palette),
+        Err(e) => {
+            eprintln!("\x1b[31mError:\x1b[0m {}", e);
+            eprintln!();
         }
     }
-
-    // Remove color codes for length calculation
-    let ansi_regex = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
-    ansi_regex.replace_all(&result, "").to_string()
 }
 
-/// Get config file path: ~/.config/twig/config.toml
 fn get_config_path() -> PathBuf {
     if let Some(proj_dirs) = ProjectDirs::from("", "", "twig") {
         proj_dirs.config_dir().join("config.toml")
@@ -676,364 +1149,468 @@ fn get_config_path() -> PathBuf {
 /// Create default config
 fn create_default_config() -> Config {
     Config {
+        theme: None,
+        styles: Palette::new(),
+        fragments: Fragments::new(),
         time: Some(TimeConfig {
-            name: None,
             format: "%H:%M:%S".to_string(),
+            ..Default::default()
         }),
-        hostname: Some(HostnameConfig { name: None }),
-        cwd: Some(CwdConfig { name: None }),
+        hostname: Some(HostnameConfig::default()),
+        cwd: Some(CwdConfig::default()),
         git: None,
         ip: None,
         battery: None,
+        exit: None,
+        cmd: None,
+        jobs: None,
+        vi: None,
         prompt: PromptConfig {
             format: "{time:cyan} {\"@\":yellow,bold} {hostname:magenta} {cwd:green} {\"$\":white,bold} ".to_string(),
             format_wide: None,
             format_narrow: None,
             width_threshold: None, // Use dynamic length-based switching by default
             padding: None, // Default: 5 characters
+            color_depth: None, // Detect from COLORTERM/TERM
+            ..Default::default()
         },
     }
 }
 
-/// Get visible length of a string (strip ANSI codes and count characters)
+/// Get visible length of a string (strip ANSI codes and count terminal columns)
 /// This helps measure the actual visual width of the prompt
-/// Handles both raw ANSI codes and shell-wrapped codes (tcsh %{...%}, zsh %{...%})
+/// Handles both raw ANSI codes and the formatter's wrappers (tcsh/zsh %{...%},
+/// bash \[...\]); text escaped for the shell counts as what it shows
 /// For multi-line prompts, returns the length of the LONGEST line
-fn visible_length(s: &str) -> usize {
-    let without_ansi = strip_ansi_codes(s);
+fn visible_length(s: &str, formatter: &dyn ShellFormatter) -> usize {
+    let clean = formatter.visible_text(&strip_ansi_codes(s));
 
-    // Also strip shell escape wrappers: %{...%} (tcsh/zsh)
-    let re = Regex::new(r"%\{[^}]*\}").unwrap();
-    let clean = re.replace_all(&without_ansi, "");
+    // Unexpanded {fill}s take no space
+    let re = Regex::new(r"\x00[^\x00]*\x00").unwrap();
+    let clean = re.replace_all(&clean, "");
 
     // For multi-line prompts, get the longest line
     // Handle both actual newlines and escaped \n sequences
     let lines: Vec<&str> = clean.split('\n').collect();
 
     lines.iter()
-        .map(|line| line.chars().count())
+        .map(|line| width::display_width(line))
         .max()
         .unwrap_or(0)
 }
 
-/// Process conditional spaces (~) in template
-/// A tilde (~) acts as a conditional space that only appears if the adjacent variable has a value.
-/// - `~{var}` - space before var if var exists
-/// - `\~` - literal tilde (escaped)
+/// Delimits the text of an unexpanded `{fill}` in rendered output
+const FILL_MARKER: char = '\0';
+
+/// Expand `{fill}` markers so each line spans the terminal width
 ///
-/// The ~ is evaluated against the variable that immediately follows it.
-fn process_conditional_spaces(template: &str, variables: &HashMap<String, String>) -> String {
-    let mut result = String::new();
-    let chars: Vec<char> = template.chars().collect();
-    let mut i = 0;
-
-    while i < chars.len() {
-        if chars[i] == '\\' && i + 1 < chars.len() && chars[i + 1] == '~' {
-            // Escaped tilde: \~ -> ~
-            result.push('~');
-            i += 2;
-        } else if chars[i] == '~' {
-            // Conditional space: look for next variable {var}
-            let remaining = &chars[i + 1..];
-
-            // Find the next variable pattern {var} or {var:color}
-            if let Some(var_name) = extract_next_variable(remaining) {
-                // Check if variable has a value
-                if variable_has_value(&var_name, variables) {
-                    result.push(' '); // Add space
+/// Fills on the same line share the free columns (earlier ones get the
+/// remainder); `reserved` columns stay free on the last line for a right
+/// prompt. Without a known width fills render as nothing. Fill texts are
+/// escaped for the shell after being repeated, so they are measured as typed.
+fn resolve_fills(output: &str, width: Option<u16>, reserved: usize, formatter: &dyn ShellFormatter) -> String {
+    if !output.contains(FILL_MARKER) {
+        return output.to_string();
+    }
+
+    let lines: Vec<&str> = output.split('\n').collect();
+    let last = lines.len() - 1;
+    let resolved: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            // Even parts are the line's own content, odd parts fill texts
+            let parts: Vec<&str> = line.split(FILL_MARKER).collect();
+            let fills = parts.len() / 2;
+            if fills == 0 {
+                return line.to_string();
+            }
+
+            let content: String = parts.iter().step_by(2).copied().collect();
+            let used = visible_length(&content, formatter) + if i == last { reserved } else { 0 };
+            let free = width.map_or(0, |w| (w as usize).saturating_sub(used));
+
+            let mut expanded = String::new();
+            for (j, part) in parts.iter().enumerate() {
+                if j % 2 == 0 {
+                    expanded.push_str(part);
+                } else {
+                    let count = free / fills + usize::from(j / 2 < free % fills);
+                    expanded.push_str(&formatter.escape_text(&width::repeat(part, count)));
                 }
-                // else: don't add space (variable is empty)
-            } else {
-                // No variable found after ~, treat as literal (or could error)
-                result.push('~');
             }
-            i += 1;
-        } else {
-            result.push(chars[i]);
-            i += 1;
-        }
-    }
+            expanded
+        })
+        .collect();
+    resolved.join("\n")
+}
 
-    result
+/// Visible length of the last line of a (multi-line) prompt
+/// This is the line a right prompt shares
+fn last_line_length(s: &str, formatter: &dyn ShellFormatter) -> usize {
+    visible_length(s.rsplit('\n').next().unwrap_or(s), formatter)
 }
 
-/// Extract the variable name from the next {var} or {var:color} pattern
-/// Returns None if no variable pattern found
-fn extract_next_variable(chars: &[char]) -> Option<String> {
-    // Skip whitespace and find the opening {
-    let mut pos = 0;
-    while pos < chars.len() && chars[pos].is_whitespace() {
-        pos += 1;
-    }
+/// Whether a right prompt fits next to the last line of the left prompt
+/// Needs one column between the sides plus `padding` (as for narrow switching)
+fn right_prompt_fits(left: &str, right: &str, width: u16, padding: i32, formatter: &dyn ShellFormatter) -> bool {
+    let combined = last_line_length(left, formatter) + 1 + visible_length(right, formatter);
+    combined as i32 + padding <= width as i32
+}
 
-    if pos >= chars.len() || chars[pos] != '{' {
-        return None;
-    }
+/// Insert text at the start of the last line of a prompt
+fn insert_at_last_line(prompt: &str, text: &str) -> String {
+    let start = prompt.rfind('\n').map_or(0, |i| i + 1);
+    format!("{}{}{}", &prompt[..start], text, &prompt[start..])
+}
 
-    // Find matching }
-    let mut end = pos + 1;
-    while end < chars.len() && chars[end] != '}' {
-        end += 1;
-    }
+/// Parse a format string, falling back to verbatim text if it is malformed
+///
+/// Parse errors are reported by `twig --validate`; the prompt itself should
+/// never fail to render.
+fn parse_template(format: &str, fragments: &Fragments) -> Template {
+    Template::parse_with(format, fragments).unwrap_or_else(|_| Template::text(format))
+}
 
-    if end >= chars.len() {
-        return None;
+/// Look up the value a placeholder source refers to
+/// Missing variables and environment variables resolve to an empty string
+fn lookup_value(source: &Source, variables: &HashMap<String, String>) -> String {
+    match source {
+        Source::Literal(text) => text.clone(),
+        Source::Env(name) => std::env::var(name).unwrap_or_default(),
+        Source::Variable(name) => variables.get(name).cloned().unwrap_or_default(),
     }
+}
 
-    // Extract content between { and }
-    let content: String = chars[pos + 1..end].iter().collect();
-
-    // Skip literals ("text":color)
-    if content.starts_with('"') {
-        return None;
+/// Check if a variable has a non-empty value
+/// Handles both regular variables and environment variables ($VAR)
+fn variable_has_value(source: &Source, variables: &HashMap<String, String>) -> bool {
+    match source {
+        Source::Literal(_) => false,
+        _ => !lookup_value(source, variables).is_empty(),
     }
-
-    // Extract variable name (before any : for colors)
-    // For environment variables like {$USER:color}, extract $USER
-    let var_name = content.split(':').next()?.to_string();
-
-    Some(var_name)
 }
 
-/// Extract all variables from a template string
-/// Returns a Vec of variable names (without colors/styles)
-/// Excludes literals and environment variables
-fn extract_all_variables(template: &str) -> Vec<String> {
-    let chars: Vec<char> = template.chars().collect();
-    let mut variables = Vec::new();
-    let mut i = 0;
-
-    while i < chars.len() {
-        if chars[i] == '{' {
-            // Find matching }
-            let mut end = i + 1;
-            while end < chars.len() && chars[end] != '}' {
-                end += 1;
-            }
+/// Check if a placeholder will show a value
+///
+/// A variable with a `?` fallback counts as present when any alternative has a
+/// value; a non-empty literal fallback always does.
+fn placeholder_has_value(placeholder: &Placeholder, variables: &HashMap<String, String>) -> bool {
+    variable_has_value(&placeholder.source, variables)
+        || placeholder.fallbacks.iter().any(|fallback| match &fallback.source {
+            Source::Literal(text) => !text.is_empty(),
+            source => variable_has_value(source, variables),
+        })
+}
 
-            if end < chars.len() {
-                let content: String = chars[i + 1..end].iter().collect();
+/// Everything needed to render a parsed template
+#[derive(Clone, Copy)]
+struct RenderContext<'a> {
+    variables: &'a HashMap<String, String>,
+    /// Source of value-mapped `styles` tables
+    config: &'a Config,
+    /// Extra style parts from matching section rules, by variable name
+    rule_styles: &'a HashMap<String, Vec<String>>,
+    /// Style every placeholder builds on and returns to (a powerline segment's colors)
+    base: &'a [String],
+    formatter: &'a dyn ShellFormatter,
+    color_depth: ColorDepth,
+    /// Hyperlink targets reported by providers, by variable name
+    links: &'a HashMap<String, String>,
+}
 
-                // Skip literals ("text":color) and environment variables ($VAR)
-                if !content.starts_with('"') && !content.starts_with('$') {
-                    // Extract variable name (before any : for colors)
-                    if let Some(var_name) = content.split(':').next() {
-                        if !var_name.is_empty() {
-                            variables.push(var_name.to_string());
-                        }
-                    }
-                }
+/// Render the powerline segment bar (if any) followed by the format template
+fn render_prompt(segments: &[Template], template: &Template, ctx: &RenderContext) -> String {
+    let mut output = render_segments(segments, ctx);
+    output.push_str(&render_template(template, ctx));
+    output
+}
 
-                i = end + 1;
-            } else {
-                i += 1;
+/// Render the first format that fits the terminal
+///
+/// A prompt fits when its longest line plus `padding` is within `width`
+/// (negative padding allows some overflow). Parts with a `!priority` (and
+/// segments with a `priority`) are dropped one at a time, lowest first, before
+/// the next format is tried. When none fits, the last and most compact format
+/// is used with everything droppable dropped. Returns the index of the chosen
+/// format and its output.
+fn render_first_fitting(
+    segments: &[Template],
+    templates: &[Template],
+    ctx: &RenderContext,
+    width: u16,
+    padding: i32,
+) -> (usize, String) {
+    let mut output = String::new();
+    for (i, template) in templates.iter().enumerate() {
+        let mut segments = segments.to_vec();
+        let mut template = template.clone();
+        loop {
+            output = render_prompt(&segments, &template, ctx);
+            if visible_length(&output, ctx.formatter) as i32 + padding <= width as i32 {
+                return (i, output);
+            }
+            if !drop_lowest_priority(&mut segments, &mut template, &ctx.config.prompt.segments) {
+                break;
             }
-        } else {
-            i += 1;
         }
     }
-
-    variables
+    (templates.len().saturating_sub(1), output)
 }
 
-/// Check if a variable has a non-empty value
-/// Handles both regular variables and environment variables ($VAR)
-fn variable_has_value(var_name: &str, variables: &HashMap<String, String>) -> bool {
-    if var_name.starts_with('$') {
-        // Environment variable
-        let env_var = &var_name[1..];
-        std::env::var(env_var).map(|v| !v.is_empty()).unwrap_or(false)
-    } else {
-        // Regular variable
-        variables
-            .get(var_name)
-            .map(|v| !v.is_empty())
-            .unwrap_or(false)
+/// Drop the part with the lowest priority from the segments and the format
+///
+/// Of parts with equal priority the rightmost goes first. Returns false when
+/// nothing is left to drop.
+fn drop_lowest_priority(segments: &mut [Template], template: &mut Template, configs: &[SegmentConfig]) -> bool {
+    // (priority, segment index (None: the format), part index (None: the whole segment))
+    let mut parts = Vec::new();
+    for (i, (segment, config)) in segments.iter().zip(configs).enumerate() {
+        if let Some(priority) = config.priority.filter(|_| !segment.nodes().is_empty()) {
+            parts.push((priority, Some(i), None));
+        }
+        parts.extend(segment.priorities().into_iter().enumerate().map(|(j, p)| (p, Some(i), Some(j))));
+    }
+    parts.extend(template.priorities().into_iter().enumerate().map(|(j, p)| (p, None, Some(j))));
+
+    let Some(&(_, segment, part)) = parts.iter().rev().min_by_key(|(priority, _, _)| *priority) else {
+        return false;
+    };
+    match (segment, part) {
+        (Some(i), None) => segments[i] = Template::default(),
+        (Some(i), Some(j)) => {
+            segments[i].drop_part(j);
+            // A segment whose variables have all been dropped goes with them
+            if segments[i].variables().is_empty() {
+                segments[i] = Template::default();
+            }
+        }
+        (None, Some(j)) => template.drop_part(j),
+        (None, None) => unreachable!("the format is never dropped as a whole"),
     }
+    true
+}
+
+/// Render `[[prompt.segments]]` as a powerline bar, skipping empty segments
+fn render_segments(segments: &[Template], ctx: &RenderContext) -> String {
+    let mut visible = Vec::new();
+
+    for (template, config) in segments.iter().zip(&ctx.config.prompt.segments) {
+        if !segment_has_value(template, ctx.variables) {
+            continue;
+        }
+        let base = segment_parts(config, &ctx.config.styles);
+        let text = render_template(template, &RenderContext { base: &base, ..*ctx });
+        visible.push(powerline::Segment {
+            text,
+            style: Style::parse_lossy(&base),
+        });
+    }
+
+    let separator = powerline::Separator::from_config(ctx.config.prompt.separator.as_deref());
+    powerline::join(&visible, &separator, ctx.formatter, ctx.color_depth)
+}
+
+/// Whether a segment is shown
+///
+/// Like an optional group: a segment with variables is skipped unless one
+/// of them has a value; a segment of only text and literals is always shown.
+/// An empty segment (dropped by priority) is never shown.
+fn segment_has_value(template: &Template, variables: &HashMap<String, String>) -> bool {
+    let has_variables = template.placeholders().any(|p| !matches!(p.source, Source::Literal(_)));
+    let has_value = !has_variables || template.nodes().iter().any(|node| node_has_value(node, variables));
+    !template.nodes().is_empty() && has_value
+}
+
+/// A segment's fg/bg as style parts, palette names resolved
+fn segment_parts(segment: &SegmentConfig, palette: &Palette) -> Vec<String> {
+    let mut parts = segment.fg.as_deref().map(style::split_spec).unwrap_or_default();
+    if let Some(bg) = &segment.bg {
+        parts.push(format!("bg:{}", bg.trim()));
+    }
+    style::resolve_lossy(&parts, palette)
 }
 
 /// Template substitution with color/style support
 /// Supports:
 /// - {var} - plain variable
-/// - {var:color} - variable with color
+/// - {var:color} - variable with color (named, #rrggbb, rgb(r,g,b) or color(n))
 /// - {var:color,style} - variable with color and style
 /// - {"text":color} - literal text with color
 /// - {$ENV_VAR} - environment variable
 /// - {$ENV_VAR:color} - environment variable with color
 /// - ~ - conditional space (only appears if adjacent variable exists)
 /// - \~ - literal tilde
+/// - [...] - optional group, dropped unless a variable inside has a value
+/// - \[ \] - literal brackets
+///
+/// Renders with the default config (no fragments, palette or rules)
+#[cfg(test)]
 fn substitute_variables(
     template: &str,
     variables: &HashMap<String, String>,
     formatter: &dyn ShellFormatter,
 ) -> String {
-    // First, process conditional spaces (~)
-    let template = process_conditional_spaces(template, variables);
-
-    // Match {anything} patterns
-    let re = Regex::new(r"\{([^}]+)\}").unwrap();
+    let config = Config::default();
+    let rule_styles = HashMap::new();
+    let links = HashMap::new();
+    let ctx = RenderContext {
+        variables,
+        config: &config,
+        rule_styles: &rule_styles,
+        base: &[],
+        formatter,
+        color_depth: ColorDepth::TrueColor,
+        links: &links,
+    };
+    resolve_fills(&render_template(&parse_template(template, &config.fragments), &ctx), None, 0, formatter)
+}
 
-    re.replace_all(&template, |caps: &regex::Captures| {
-        let content = &caps[1];
+/// Render a parsed template
+fn render_template(template: &Template, ctx: &RenderContext) -> String {
+    let mut output = String::new();
+    render_nodes(template.nodes(), ctx, &mut output);
+    output
+}
 
-        // Check if it's a literal: "text":color
-        if content.starts_with('"') {
-            return handle_literal(content, formatter);
+/// Render a list of nodes (the template body or a group's contents)
+fn render_nodes(nodes: &[Node], ctx: &RenderContext, output: &mut String) {
+    for (i, node) in nodes.iter().enumerate() {
+        match node {
+            Node::Text { text, .. } => output.push_str(&ctx.formatter.escape_template_text(text)),
+            Node::ConditionalSpace { .. } => {
+                // The space belongs to the next placeholder or group (skipping whitespace)
+                let target = nodes[i + 1..].iter().find(|n| !n.is_blank());
+                if target.is_some_and(|n| node_has_value(n, ctx.variables)) {
+                    output.push(' ');
+                }
+            }
+            Node::Placeholder(p) => output.push_str(&render_placeholder(p, ctx)),
+            Node::Group { nodes, .. } => {
+                if node_has_value(node, ctx.variables) {
+                    render_nodes(nodes, ctx, output);
+                }
+            }
+            Node::Fill { text, style, .. } => {
+                // Expanded by resolve_fills once the whole prompt is rendered
+                let marker = format!("{0}{1}{0}", FILL_MARKER, text);
+                match style {
+                    Some(style) => output.push_str(&colorize(&marker, &style.parts, ctx)),
+                    None => output.push_str(&marker),
+                }
+            }
         }
-
-        // Otherwise it's a variable: var or var:color or var:color,style
-        handle_variable(content, variables, formatter)
-    })
-    .to_string()
+    }
 }
 
-/// Handle literal text: "text":color or "text":color,style
-fn handle_literal(content: &str, formatter: &dyn ShellFormatter) -> String {
-    // Parse: "text":color or "text":color,style
-    if let Some(colon_pos) = content.find(':') {
-        let text_part = &content[..colon_pos];
-        let style_part = &content[colon_pos + 1..];
-
-        // Extract text from quotes
-        let text = text_part.trim_matches('"');
-
-        // Apply color/style
-        colorize(text, style_part, formatter)
-    } else {
-        // No color specified, just remove quotes
-        content.trim_matches('"').to_string()
+/// Whether a node contributes a value: a variable with a non-empty value, or
+/// a group containing at least one such variable (at any depth)
+fn node_has_value(node: &Node, variables: &HashMap<String, String>) -> bool {
+    match node {
+        Node::Placeholder(p) => placeholder_has_value(p, variables),
+        Node::Group { nodes, .. } => nodes.iter().any(|n| node_has_value(n, variables)),
+        _ => false,
     }
 }
 
-/// Handle variable: var or var:color or var:color,style
-/// Also handles environment variables: $VAR or $VAR:color
-fn handle_variable(
-    content: &str,
-    variables: &HashMap<String, String>,
-    formatter: &dyn ShellFormatter,
-) -> String {
-    // Parse: var or var:color or var:color,style
-    let parts: Vec<&str> = content.split(':').collect();
-
-    let var_name = parts[0];
-    let style_spec = parts.get(1).copied();
-
-    // Get variable value
-    let value = if var_name.starts_with('$') {
-        // Environment variable: {$USER}, {$HOME}, etc.
-        let env_var = &var_name[1..]; // Strip the '$'
-        std::env::var(env_var).unwrap_or_else(|_| String::new()) // Empty string if not found
-    } else {
-        // Regular variable from config
-        variables
-            .get(var_name)
-            .cloned()
-            .unwrap_or_else(String::new) // Return empty string if variable not found
+/// Render a single placeholder: literal, variable or environment variable
+fn render_placeholder(placeholder: &Placeholder, ctx: &RenderContext) -> String {
+    // First alternative with a value: the source itself or one of its `?` fallbacks
+    let chosen = std::iter::once(placeholder)
+        .chain(&placeholder.fallbacks)
+        .find(|alt| !lookup_value(&alt.source, ctx.variables).is_empty())
+        .unwrap_or(placeholder);
+    let value = lookup_value(&chosen.source, ctx.variables);
+
+    // Apply color/style if specified, otherwise the section's value-mapped style.
+    // A fallback without its own style uses the placeholder's style.
+    let mut parts = match chosen.style.as_ref().or(placeholder.style.as_ref()) {
+        Some(style) => style.parts.clone(),
+        None => mapped_style(&chosen.source, &value, ctx).unwrap_or_default(),
     };
 
-    // Apply color/style if specified
-    if let Some(style) = style_spec {
-        colorize(&value, style, formatter)
-    } else {
-        value
+    // Matching rules add their style on top (later colors override earlier ones)
+    if let Source::Variable(name) = &chosen.source {
+        if let Some(extra) = ctx.rule_styles.get(name) {
+            parts.extend(extra.iter().cloned());
+        }
     }
-}
 
-/// Apply ANSI color and style codes to text
-/// style_spec can be: "color" or "color,style" or "color,style1,style2"
-fn colorize(text: &str, style_spec: &str, formatter: &dyn ShellFormatter) -> String {
-    let parts: Vec<&str> = style_spec.split(',').map(|s| s.trim()).collect();
+    // Filters run on the value before styling; value-mapped styles above
+    // match the unfiltered value. Empty values stay empty.
+    let text = if value.is_empty() {
+        value
+    } else {
+        filter::apply_all(&chosen.filters, &value)
+    };
 
-    let mut codes = Vec::new();
+    // Values are shown literally: escape anything the shell would expand
+    let text = ctx.formatter.escape_text(&text);
 
-    for part in parts {
-        if let Some(code) = get_ansi_code(part) {
-            codes.push(code);
-        }
-    }
+    // `link` makes the value a hyperlink to the target its provider reported
+    let link = match &chosen.source {
+        Source::Variable(name) if !text.is_empty() => ctx.links.get(name),
+        _ => None,
+    };
+    let link = link.filter(|_| style::resolve_lossy(&parts, &ctx.config.styles).iter().any(|p| p == style::LINK));
 
-    if codes.is_empty() {
-        // No valid codes, return text as-is
-        text.to_string()
+    let text = if parts.is_empty() {
+        text
     } else {
-        // Build ANSI codes
-        let ansi_code = format!("\x1b[{}m", codes.join(";"));
-        let reset_code = "\x1b[0m";
+        colorize(&text, &parts, ctx)
+    };
 
-        // Use formatter to wrap codes appropriately for the shell
-        formatter.format_ansi(&ansi_code, text, reset_code)
+    match link {
+        Some(url) => ctx.formatter.format_link(url, &text),
+        None => text,
     }
 }
 
-/// Convert color/style name to ANSI code
-fn get_ansi_code(name: &str) -> Option<&'static str> {
-    match name {
-        // Basic colors (30-37)
-        "black" => Some("30"),
-        "red" => Some("31"),
-        "green" => Some("32"),
-        "yellow" => Some("33"),
-        "blue" => Some("34"),
-        "magenta" => Some("35"),
-        "cyan" => Some("36"),
-        "white" => Some("37"),
-
-        // Bright colors (90-97)
-        "bright_black" | "gray" | "grey" => Some("90"),
-        "bright_red" => Some("91"),
-        "bright_green" => Some("92"),
-        "bright_yellow" => Some("93"),
-        "bright_blue" => Some("94"),
-        "bright_magenta" => Some("95"),
-        "bright_cyan" => Some("96"),
-        "bright_white" => Some("97"),
-
-        // Styles
-        "bold" => Some("1"),
-        "italic" => Some("3"),
-        "underline" => Some("4"),
-        "normal" => Some("0"),
-
+/// Style from the `styles` table of the variable's config section
+///
+/// Only provider variables with a value are looked up, so an empty variable
+/// never picks up the `default` style.
+fn mapped_style(source: &Source, value: &str, ctx: &RenderContext) -> Option<Vec<String>> {
+    match source {
+        Source::Variable(name) if !value.is_empty() => {
+            ctx.config.value_style(name, value).map(style::split_spec)
+        }
         _ => None,
     }
 }
 
-/// Discover all variables used in a template (excluding $ENV vars and literals)
-fn discover_variables(template: &str) -> Vec<String> {
-    let re = Regex::new(r"\{([^}]+)\}").unwrap();
-    let mut vars = Vec::new();
-
-    for cap in re.captures_iter(template) {
-        let content = &cap[1];
-
-        // Skip literals ("text":color)
-        if content.starts_with('"') {
-            continue;
-        }
-
-        // Skip environment variables ($VAR)
-        if content.starts_with('$') {
-            continue;
-        }
-
-        // Extract variable name (before any : for colors)
-        let var_name = content.split(':').next().unwrap();
-
-        if !vars.contains(&var_name.to_string()) {
-            vars.push(var_name.to_string());
+/// Apply ANSI color and style codes to text
+/// parts are the style list of a placeholder: ["color"] or ["color", "style1", "style2"]
+/// Truecolor and 256-color parts are downsampled to the context's color depth
+fn colorize(text: &str, parts: &[String], ctx: &RenderContext) -> String {
+    // Palette names from [styles] take precedence over built-in names
+    let parts: Vec<String> = ctx.base.iter().chain(parts).cloned().collect();
+    let parts = style::resolve_lossy(&parts, &ctx.config.styles);
+    match Style::parse_lossy(&parts).sgr_params(ctx.color_depth) {
+        Some(params) => {
+            // Build ANSI codes
+            let ansi_code = format!("\x1b[{}m", params);
+
+            // Inside a segment, reset back to the segment's colors
+            let reset_code = match Style::parse_lossy(ctx.base).sgr_params(ctx.color_depth) {
+                Some(base) => format!("\x1b[0m\x1b[{}m", base),
+                None => "\x1b[0m".to_string(),
+            };
+
+            // Use formatter to wrap codes appropriately for the shell
+            ctx.formatter.format_ansi(&ansi_code, text, &reset_code)
         }
+        // No valid codes, return text as-is
+        None => text.to_string(),
     }
-
-    vars
 }
 
 /// Apply default configs for variables used in template but missing config sections
-fn apply_implicit_sections(config: &mut Config, template: &str) {
+fn apply_implicit_sections(config: &mut Config, template: &Template) {
     let registry = providers::ProviderRegistry::new();
-    let vars = discover_variables(template);
 
-    for var in vars {
-        let prefix = var.split('_').next().unwrap_or(&var);
+    for var in template.variables() {
+        let prefix = var.split('_').next().unwrap_or(var);
 
         if let Some(provider) = registry.get_by_section(prefix) {
             let defaults = provider.default_config();
@@ -1050,7 +1627,6 @@ fn apply_implicit_sections(config: &mut Config, template: &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::shell::RawFormatter;
 
     /// Helper to create a simple variable map for testing
     fn make_vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
@@ -1060,6 +1636,26 @@ mod tests {
             .collect()
     }
 
+    /// Helper to render a template with the default config, leaving fills unexpanded
+    fn render_with(
+        template: &str,
+        variables: &HashMap<String, String>,
+        formatter: &dyn ShellFormatter,
+        color_depth: ColorDepth,
+    ) -> String {
+        let config = Config::default();
+        let ctx = RenderContext {
+            variables,
+            config: &config,
+            rule_styles: &HashMap::new(),
+            base: &[],
+            formatter,
+            color_depth,
+            links: &HashMap::new(),
+        };
+        render_template(&parse_template(template, &Fragments::new()), &ctx)
+    }
+
     #[test]
     fn test_conditional_space_with_value() {
         let vars = make_vars(&[("cwd", "/home/user"), ("git_branch", "main")]);
@@ -1196,62 +1792,145 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_next_variable() {
-        // Test basic variable
-        let chars: Vec<char> = "{var}".chars().collect();
-        assert_eq!(extract_next_variable(&chars), Some("var".to_string()));
+    fn test_group_with_value() {
+        let vars = make_vars(&[("cwd", "/home/user"), ("git_branch", "main")]);
+
+        let result = substitute_variables("{cwd}[ on {git_branch}]", &vars, &RawFormatter);
+        assert_eq!(result, "/home/user on main");
+    }
+
+    #[test]
+    fn test_group_without_value() {
+        let vars = make_vars(&[("cwd", "/home/user"), ("git_branch", "")]);
+
+        // Literals and styling inside the group are dropped along with the variable
+        let result = substitute_variables("{cwd}[ on {git_branch:magenta}{\")\":red}]", &vars, &RawFormatter);
+        assert_eq!(result, "/home/user");
+    }
+
+    #[test]
+    fn test_group_any_variable_keeps_group() {
+        let vars = make_vars(&[("git_tracking", "(ahead.2)")]);
+
+        let result = substitute_variables("[({git_branch}{git_tracking})]", &vars, &RawFormatter);
+        assert_eq!(result, "((ahead.2))");
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let template = "{cwd}[ ({git_branch}[ {git_tracking}])]";
+
+        let vars = make_vars(&[("cwd", "~"), ("git_branch", "main")]);
+        assert_eq!(substitute_variables(template, &vars, &RawFormatter), "~ (main)");
 
-        // Test variable with color
-        let chars: Vec<char> = "{var:red}".chars().collect();
-        assert_eq!(extract_next_variable(&chars), Some("var".to_string()));
+        let vars = make_vars(&[("cwd", "~"), ("git_branch", "main"), ("git_tracking", "+1")]);
+        assert_eq!(substitute_variables(template, &vars, &RawFormatter), "~ (main +1)");
 
-        // Test variable with whitespace before
-        let chars: Vec<char> = "  {var}".chars().collect();
-        assert_eq!(extract_next_variable(&chars), Some("var".to_string()));
+        let vars = make_vars(&[("cwd", "~")]);
+        assert_eq!(substitute_variables(template, &vars, &RawFormatter), "~");
+    }
+
+    #[test]
+    fn test_conditional_space_before_group() {
+        let template = "{cwd}~[({git_branch})]";
+
+        let vars = make_vars(&[("cwd", "~"), ("git_branch", "main")]);
+        assert_eq!(substitute_variables(template, &vars, &RawFormatter), "~ (main)");
+
+        let vars = make_vars(&[("cwd", "~")]);
+        assert_eq!(substitute_variables(template, &vars, &RawFormatter), "~");
+    }
+
+    #[test]
+    fn test_group_in_shell_modes() {
+        use crate::shell::{BashFormatter, TcshFormatter};
+
+        let vars = make_vars(&[("cwd", "/tmp")]);
+        let template = "{cwd}[ {\"on\":red} {git_branch:magenta}]";
+        assert_eq!(substitute_variables(template, &vars, &BashFormatter), "/tmp");
+        assert_eq!(substitute_variables(template, &vars, &TcshFormatter), "/tmp");
+    }
+
+    #[test]
+    fn test_group_visible_length() {
+        let vars = make_vars(&[("cwd", "/tmp")]);
+
+        // Dropped groups don't count toward the measured prompt length
+        let result = substitute_variables("{cwd:green}[ on {git_branch:magenta}]", &vars, &RawFormatter);
+        assert_eq!(visible_length(&result, &RawFormatter), 4);
+    }
+
+    #[test]
+    fn test_literal_with_delimiters() {
+        let vars = make_vars(&[]);
+        let formatter = RawFormatter;
 
-        // Test literal (should return None)
-        let chars: Vec<char> = "{\"text\":color}".chars().collect();
-        assert_eq!(extract_next_variable(&chars), None);
+        // Closing brace and colon inside a literal are part of the text
+        assert_eq!(substitute_variables("{\"}\"}", &vars, &formatter), "}");
+        assert_eq!(
+            substitute_variables("{\"a:b\":red}", &vars, &formatter),
+            "\x1b[31ma:b\x1b[0m"
+        );
+    }
 
-        // Test no variable
-        let chars: Vec<char> = "no var here".chars().collect();
-        assert_eq!(extract_next_variable(&chars), None);
+    #[test]
+    fn test_malformed_template_renders_verbatim() {
+        let vars = make_vars(&[("cwd", "/home/user")]);
+        let formatter = RawFormatter;
 
-        // Test environment variable
-        let chars: Vec<char> = "{$USER}".chars().collect();
-        assert_eq!(extract_next_variable(&chars), Some("$USER".to_string()));
+        let result = substitute_variables("{cwd} {git_branch", &vars, &formatter);
+        assert_eq!(result, "{cwd} {git_branch");
     }
 
     #[test]
     fn test_variable_has_value() {
         let vars = make_vars(&[("key", "value"), ("empty", "")]);
+        let var = |name: &str| Source::Variable(name.to_string());
+        let env = |name: &str| Source::Env(name.to_string());
 
         // Regular variable with value
-        assert!(variable_has_value("key", &vars));
+        assert!(variable_has_value(&var("key"), &vars));
 
         // Regular variable that's empty
-        assert!(!variable_has_value("empty", &vars));
+        assert!(!variable_has_value(&var("empty"), &vars));
 
         // Regular variable that doesn't exist
-        assert!(!variable_has_value("missing", &vars));
+        assert!(!variable_has_value(&var("missing"), &vars));
 
         // Environment variable (testing with a commonly available one)
         std::env::set_var("TEST_VAR", "test_value");
-        assert!(variable_has_value("$TEST_VAR", &vars));
+        assert!(variable_has_value(&env("TEST_VAR"), &vars));
 
         // Environment variable that's empty
         std::env::set_var("TEST_VAR_EMPTY", "");
-        assert!(!variable_has_value("$TEST_VAR_EMPTY", &vars));
+        assert!(!variable_has_value(&env("TEST_VAR_EMPTY"), &vars));
 
         // Cleanup
         std::env::remove_var("TEST_VAR");
         std::env::remove_var("TEST_VAR_EMPTY");
     }
 
+    #[test]
+    fn test_validation_prompt_uses_config() {
+        let config: Config = toml::from_str(
+            "[prompt]\nformat = \"{@where}\"\n\n[fragments]\nwhere = \"{cwd:accent} $\"\n\n[styles]\naccent = \"red\"\n",
+        )
+        .unwrap();
+        let result = providers::CollectResult {
+            variables: make_vars(&[("cwd", "/tmp")]),
+            numbers: HashMap::new(),
+            links: HashMap::new(),
+            timings: Vec::new(),
+        };
+
+        // The fragment is expanded and the palette name resolved
+        assert_eq!(render_validation_prompt(&config, result), "\x1b[31m/tmp\x1b[0m $");
+    }
+
     #[test]
     fn test_validate_format_syntax_valid() {
         let format = "{time:cyan} {hostname:yellow} {cwd:green} $ ";
-        let result = validate_format_syntax(format);
+        let result = validate_format_syntax(format, &Fragments::new());
         assert!(result.is_ok());
         let vars = result.unwrap();
         assert_eq!(vars.len(), 3);
@@ -1263,7 +1942,7 @@ mod tests {
     #[test]
     fn test_validate_format_syntax_with_literals() {
         let format = "{time:cyan} {\"@\":yellow} {hostname:magenta} $ ";
-        let result = validate_format_syntax(format);
+        let result = validate_format_syntax(format, &Fragments::new());
         assert!(result.is_ok());
         let vars = result.unwrap();
         // Literals should not be counted as variables
@@ -1273,17 +1952,33 @@ mod tests {
     #[test]
     fn test_validate_format_syntax_with_env_vars() {
         let format = "{time:cyan} {$USER:yellow} {cwd:green} $ ";
-        let result = validate_format_syntax(format);
+        let result = validate_format_syntax(format, &Fragments::new());
         assert!(result.is_ok());
         let vars = result.unwrap();
         // Env vars should not be counted as regular variables
         assert_eq!(vars.len(), 2);
     }
 
+    #[test]
+    fn test_validate_format_syntax_invalid() {
+        let result = validate_format_syntax("{cwd:green} {git_branch", &Fragments::new());
+        assert_eq!(result.unwrap_err(), "unclosed '{' at line 1, column 13");
+    }
+
+    #[test]
+    fn test_validate_stray_bracket() {
+        let result = validate_format_syntax("{cwd}] $ ", &Fragments::new());
+        assert_eq!(result.unwrap_err(), "unexpected ']' (use \\] for a literal bracket) at line 1, column 6");
+
+        // The prompt itself falls back to the format's text
+        let vars = make_vars(&[("cwd", "/tmp")]);
+        assert_eq!(substitute_variables("{cwd}] $ ", &vars, &RawFormatter), "{cwd}] $ ");
+    }
+
     #[test]
     fn test_validate_colors_and_styles_valid() {
         let format = "{time:cyan} {hostname:yellow,bold} {cwd:green} $ ";
-        let result = validate_colors_and_styles(format);
+        let result = validate_colors_and_styles(format, &Palette::new(), &Fragments::new());
         assert!(result.is_ok());
         let count = result.unwrap();
         assert_eq!(count, 4); // cyan, yellow, bold, green
@@ -1292,7 +1987,7 @@ mod tests {
     #[test]
     fn test_validate_colors_and_styles_invalid() {
         let format = "{time:invalid_color} {hostname:yellow} $ ";
-        let result = validate_colors_and_styles(format);
+        let result = validate_colors_and_styles(format, &Palette::new(), &Fragments::new());
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("invalid_color"));
     }
@@ -1300,12 +1995,910 @@ mod tests {
     #[test]
     fn test_validate_colors_and_styles_bright_colors() {
         let format = "{time:bright_cyan} {hostname:bright_yellow} $ ";
-        let result = validate_colors_and_styles(format);
+        let result = validate_colors_and_styles(format, &Palette::new(), &Fragments::new());
         assert!(result.is_ok());
         let count = result.unwrap();
         assert_eq!(count, 2);
     }
 
+    #[test]
+    fn test_validate_colors_and_styles_extended_colors() {
+        let format = "{cwd:#88c0d0} {git_branch:rgb(180,142,173),bold} {time:color(208)} $ ";
+        assert_eq!(validate_colors_and_styles(format, &Palette::new(), &Fragments::new()), Ok(4));
+    }
+
+    #[test]
+    fn test_validate_colors_and_styles_malformed_colors() {
+        let result = validate_colors_and_styles("{cwd:#88c0d}", &Palette::new(), &Fragments::new());
+        assert!(result.unwrap_err().contains("#88c0d"));
+
+        let result = validate_colors_and_styles("{cwd:rgb(180,142)}", &Palette::new(), &Fragments::new());
+        assert!(result.unwrap_err().contains("rgb(180,142)"));
+
+        let result = validate_colors_and_styles("{time:color(300)}", &Palette::new(), &Fragments::new());
+        assert!(result.unwrap_err().contains("color(300)"));
+    }
+
+    #[test]
+    fn test_truecolor_rendering() {
+        let vars = make_vars(&[("cwd", "/home/user")]);
+        let formatter = RawFormatter;
+
+        let result = substitute_variables("{cwd:#88c0d0,bold}", &vars, &formatter);
+        assert_eq!(result, "\x1b[38;2;136;192;208;1m/home/user\x1b[0m");
+    }
+
+    #[test]
+    fn test_background_and_attributes() {
+        let vars = make_vars(&[("cwd", "/home/user")]);
+
+        let result = substitute_variables("{cwd:white,bg:blue,dim,reverse}", &vars, &RawFormatter);
+        assert_eq!(result, "\x1b[37;44;2;7m/home/user\x1b[0m");
+
+        let result = substitute_variables("{\"x\":on_bright_black,strikethrough,overline,blink}", &vars, &RawFormatter);
+        assert_eq!(result, "\x1b[100;9;53;5mx\x1b[0m");
+    }
+
+    #[test]
+    fn test_background_in_shell_modes() {
+        use crate::shell::{BashFormatter, TcshFormatter, ZshFormatter};
+
+        let vars = make_vars(&[("cwd", "/tmp")]);
+        let template = "{cwd:bg:#2e3440,bold}";
+
+        assert_eq!(
+            substitute_variables(template, &vars, &BashFormatter),
+            "\\[\x1b[48;2;46;52;64;1m\\]/tmp\\[\x1b[0m\\]"
+        );
+        assert_eq!(
+            substitute_variables(template, &vars, &ZshFormatter),
+            "%{\x1b[48;2;46;52;64;1m%}/tmp%{\x1b[0m%}"
+        );
+        assert_eq!(
+            substitute_variables(template, &vars, &TcshFormatter),
+            "%{\x1b[48;2;46;52;64;1m%}/tmp%{\x1b[0m%}"
+        );
+    }
+
+    #[test]
+    fn test_value_mapped_styles() {
+        let config: Config = toml::from_str(
+            r#"
+            [hostname]
+            styles = { skyfall = "magenta", "prod-*" = "red,bold", default = "white" }
+
+            [git]
+            styles = { main = "green", "release/*" = "rgb(255, 0, 0)" }
+
+            [prompt]
+            format = "{hostname}"
+            "#,
+        )
+        .unwrap();
+        let formatter = RawFormatter;
+        let render = |template: &str, vars: &HashMap<String, String>| {
+            let ctx = RenderContext {
+                variables: vars,
+                config: &config,
+                rule_styles: &HashMap::new(),
+                base: &[],
+                formatter: &formatter,
+                color_depth: ColorDepth::TrueColor,
+                links: &HashMap::new(),
+            };
+            render_template(&parse_template(template, &Fragments::new()), &ctx)
+        };
+
+        // Exact, glob and default matches
+        let vars = make_vars(&[("hostname", "skyfall")]);
+        assert_eq!(render("{hostname}", &vars), "\x1b[35mskyfall\x1b[0m");
+        let vars = make_vars(&[("hostname", "prod-db-1")]);
+        assert_eq!(render("{hostname}", &vars), "\x1b[31;1mprod-db-1\x1b[0m");
+        let vars = make_vars(&[("hostname", "laptop")]);
+        assert_eq!(render("{hostname}", &vars), "\x1b[37mlaptop\x1b[0m");
+
+        // An explicit template style wins over the mapping
+        let vars = make_vars(&[("hostname", "skyfall")]);
+        assert_eq!(render("{hostname:cyan}", &vars), "\x1b[36mskyfall\x1b[0m");
+
+        // Protected branches; other branches have no default and stay plain
+        let vars = make_vars(&[("git_branch", "release/1.2")]);
+        assert_eq!(render("{git_branch}", &vars), "\x1b[38;2;255;0;0mrelease/1.2\x1b[0m");
+        let vars = make_vars(&[("git_branch", "feature")]);
+        assert_eq!(render("{git_branch}", &vars), "feature");
+
+        // Empty values never pick up the default style
+        let vars = make_vars(&[]);
+        assert_eq!(render("{hostname}", &vars), "");
+    }
+
+    #[test]
+    fn test_rules_rendering() {
+        let config: Config = toml::from_str(
+            r#"
+            [[battery.rules]]
+            when = "percentage < 20"
+            style = "red,bold"
+
+            [[battery.rules]]
+            when = "percentage >= 95"
+            hide = true
+
+            [[git.rules]]
+            when = "status_staged > 10"
+            style = "bold"
+
+            [prompt]
+            format = "{battery_percentage}"
+            "#,
+        )
+        .unwrap();
+        let formatter = RawFormatter;
+        let render = |template: &str, pairs: &[(&str, &str)], numbers: &[(&str, f64)]| {
+            let mut vars = make_vars(pairs);
+            let numbers = numbers.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+            let rule_styles = rules::apply_rules(&config, &mut vars, &numbers);
+            let ctx = RenderContext {
+                variables: &vars,
+                config: &config,
+                rule_styles: &rule_styles,
+                base: &[],
+                formatter: &formatter,
+                color_depth: ColorDepth::TrueColor,
+                links: &HashMap::new(),
+            };
+            render_template(&parse_template(template, &Fragments::new()), &ctx)
+        };
+
+        // Rule style overrides the template color
+        assert_eq!(
+            render("{battery_percentage:green}", &[("battery_percentage", "15%")], &[("battery_percentage", 15.0)]),
+            "\x1b[31;1m15%\x1b[0m"
+        );
+        assert_eq!(
+            render("{battery_percentage:green}", &[("battery_percentage", "85%")], &[("battery_percentage", 85.0)]),
+            "\x1b[32m85%\x1b[0m"
+        );
+
+        // Hidden variables collapse their group
+        assert_eq!(
+            render("[bat {battery_percentage}]$", &[("battery_percentage", "98%")], &[("battery_percentage", 98.0)]),
+            "$"
+        );
+
+        // Attributes are added to the template style
+        let staged = render("{git_status_staged:yellow}", &[("git_status_staged", ":+12")], &[("git_status_staged", 12.0)]);
+        assert_eq!(staged, "\x1b[33;1m:+12\x1b[0m");
+        let staged = render("{git_status_staged:yellow}", &[("git_status_staged", ":+3")], &[("git_status_staged", 3.0)]);
+        assert_eq!(staged, "\x1b[33m:+3\x1b[0m");
+    }
+
+    #[test]
+    fn test_validate_rules() {
+        let parse = |toml: &str| -> Config {
+            toml::from_str(&format!("{}\n[prompt]\nformat = \"x\"", toml)).unwrap()
+        };
+
+        let config = parse("[[battery.rules]]\nwhen = \"percentage < 20\"\nstyle = \"red\"\n[[battery.rules]]\nwhen = \"percentage > 95\"\nhide = true");
+        assert_eq!(validate_rules(&config), Ok(2));
+
+        let config = parse("[[battery.rules]]\nwhen = \"percentage <\"\nstyle = \"red\"");
+        assert!(validate_rules(&config).unwrap_err().starts_with("[[battery.rules]] #1"));
+
+        let config = parse("[[git.rules]]\nwhen = \"status_staged > 10\"\nstyle = \"purple\"");
+        assert!(validate_rules(&config).unwrap_err().contains("purple"));
+
+        let config = parse("[[git.rules]]\nwhen = \"status_staged > 10\"");
+        assert!(validate_rules(&config).unwrap_err().contains("style or hide"));
+    }
+
+    #[test]
+    fn test_filters_rendering() {
+        let vars = make_vars(&[
+            ("cwd", "/home/me/src/twig"),
+            ("git_branch", "feature/very-long-branch-name"),
+            ("hostname", "prod-web-1"),
+        ]);
+        let formatter = RawFormatter;
+
+        assert_eq!(substitute_variables("{cwd|basename}", &vars, &formatter), "twig");
+        assert_eq!(
+            substitute_variables("{git_branch|truncate(10,\"…\")}", &vars, &formatter),
+            "feature/ve…"
+        );
+        assert_eq!(
+            substitute_variables("{hostname|replace(\"prod-\",\"\")}", &vars, &formatter),
+            "web-1"
+        );
+
+        // Chained, then styled: codes wrap the filtered text
+        assert_eq!(
+            substitute_variables("{cwd|basename|upper:green}", &vars, &formatter),
+            "\x1b[32mTWIG\x1b[0m"
+        );
+
+        // Environment variables and literals
+        std::env::set_var("TWIG_TEST_FILTER_USER", "jeff");
+        assert_eq!(substitute_variables("{$TWIG_TEST_FILTER_USER|upper}", &vars, &formatter), "JEFF");
+        assert_eq!(substitute_variables("{\"9:05\"|pad_left(6)}", &vars, &formatter), "  9:05");
+
+        // Missing variables stay empty (no padding) so groups still collapse
+        assert_eq!(substitute_variables("[<{missing|pad_left(8)}>]", &vars, &formatter), "");
+
+        // Unknown filters are skipped when rendering
+        assert_eq!(substitute_variables("{cwd|bogus|basename}", &vars, &formatter), "twig");
+    }
+
+    #[test]
+    fn test_validate_filters() {
+        assert_eq!(validate_filters("{cwd|basename} {git_branch|truncate(20,\"…\")|upper}", &Fragments::new()), Ok(3));
+        assert_eq!(validate_filters("{cwd}", &Fragments::new()), Ok(0));
+
+        let err = validate_filters("{cwd} {cwd|basenme}", &Fragments::new()).unwrap_err();
+        assert!(err.contains("Unknown filter: 'basenme'"));
+        assert!(err.ends_with("at line 1, column 12"));
+
+        assert!(validate_filters("{cwd|truncate}", &Fragments::new()).unwrap_err().contains("1 to 2 arguments"));
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let formatter = RawFormatter;
+        let vars = make_vars(&[("cwd", "/tmp"), ("ip_interface", "wlan0")]);
+
+        // Missing variable: literal fallback
+        assert_eq!(substitute_variables("{git_branch?\"no-repo\"}", &vars, &formatter), "no-repo");
+
+        // Chains stop at the first alternative with a value
+        assert_eq!(substitute_variables("{ip_address?ip_interface?\"offline\"}", &vars, &formatter), "wlan0");
+        let vars_ip = make_vars(&[("ip_address", "10.0.0.2"), ("ip_interface", "wlan0")]);
+        assert_eq!(substitute_variables("{ip_address?ip_interface?\"offline\"}", &vars_ip, &formatter), "10.0.0.2");
+        assert_eq!(substitute_variables("{ip_address?missing?\"offline\"}", &vars, &formatter), "offline");
+
+        // Environment variables
+        std::env::remove_var("TWIG_TEST_FALLBACK_VENV");
+        assert_eq!(substitute_variables("{$TWIG_TEST_FALLBACK_VENV?\"-\"}", &vars, &formatter), "-");
+
+        // The fallback has its own style; without one it uses the placeholder's
+        assert_eq!(
+            substitute_variables("{git_branch:magenta?\"no-repo\":bright_black}", &vars, &formatter),
+            "\x1b[90mno-repo\x1b[0m"
+        );
+        assert_eq!(
+            substitute_variables("{git_branch:magenta?\"no-repo\"}", &vars, &formatter),
+            "\x1b[35mno-repo\x1b[0m"
+        );
+        let vars_git = make_vars(&[("git_branch", "main")]);
+        assert_eq!(
+            substitute_variables("{git_branch:magenta?\"no-repo\":bright_black}", &vars_git, &formatter),
+            "\x1b[35mmain\x1b[0m"
+        );
+
+        // Each alternative has its own filters
+        assert_eq!(substitute_variables("{missing|upper?cwd|basename}", &vars, &formatter), "tmp");
+    }
+
+    #[test]
+    fn test_fallbacks_count_as_present() {
+        let formatter = RawFormatter;
+        let vars = make_vars(&[("cwd", "/tmp")]);
+
+        // Conditional space and groups see the fallback
+        assert_eq!(substitute_variables("{cwd}~{git_branch?\"no-repo\"}", &vars, &formatter), "/tmp no-repo");
+        assert_eq!(substitute_variables("{cwd}[ on {git_branch?\"-\"}]", &vars, &formatter), "/tmp on -");
+
+        // Variable fallbacks only count when they have a value
+        assert_eq!(substitute_variables("{cwd}~{git_branch?git_tracking}", &vars, &formatter), "/tmp");
+        assert_eq!(substitute_variables("{cwd}[ on {git_branch?git_tracking}]", &vars, &formatter), "/tmp");
+    }
+
+    #[test]
+    fn test_palette_rendering() {
+        let config: Config = toml::from_str(
+            r##"
+            [styles]
+            warn = "red,bold"
+            vcs = "#b48ead"
+            green = "#a3be8c"
+            branch = "vcs,italic"
+
+            [git]
+            styles = { main = "branch" }
+
+            [prompt]
+            format = "{git_status_unstaged:warn}"
+            "##,
+        )
+        .unwrap();
+        let formatter = RawFormatter;
+        let vars = make_vars(&[("git_status_unstaged", ":+3"), ("git_branch", "main"), ("cwd", "/tmp")]);
+        let render = |template: &str| {
+            let ctx = RenderContext {
+                variables: &vars,
+                config: &config,
+                rule_styles: &HashMap::new(),
+                base: &[],
+                formatter: &formatter,
+                color_depth: ColorDepth::TrueColor,
+                links: &HashMap::new(),
+            };
+            render_template(&parse_template(template, &Fragments::new()), &ctx)
+        };
+
+        assert_eq!(render("{git_status_unstaged:warn}"), "\x1b[31;1m:+3\x1b[0m");
+        assert_eq!(render("{cwd:bg:vcs}"), "\x1b[48;2;180;142;173m/tmp\x1b[0m");
+        // Palette names shadow built-in colors
+        assert_eq!(render("{cwd:green}"), "\x1b[38;2;163;190;140m/tmp\x1b[0m");
+        // Value-mapped styles resolve through the palette too
+        assert_eq!(render("{git_branch}"), "\x1b[38;2;180;142;173;3mmain\x1b[0m");
+    }
+
+    fn segment_config() -> Config {
+        toml::from_str(
+            r#"
+            [prompt]
+            format = " $ "
+            separator = "ascii"
+
+            [[prompt.segments]]
+            format = " {hostname} "
+            fg = "black"
+            bg = "blue"
+
+            [[prompt.segments]]
+            format = " {git_branch} {git_status_unstaged:red} "
+            fg = "black"
+            bg = "yellow"
+
+            [[prompt.segments]]
+            format = " {cwd} "
+            fg = "white"
+            bg = "bright_black"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_powerline_segments() {
+        let config = segment_config();
+        let segments: Vec<Template> = config.prompt.segments.iter().map(|s| parse_template(&s.format, &Fragments::new())).collect();
+        let formatter = RawFormatter;
+        let render = |vars: &HashMap<String, String>| {
+            let ctx = RenderContext {
+                variables: vars,
+                config: &config,
+                rule_styles: &HashMap::new(),
+                base: &[],
+                formatter: &formatter,
+                color_depth: ColorDepth::Ansi16,
+                links: &HashMap::new(),
+            };
+            render_prompt(&segments, &parse_template(&config.prompt.format, &Fragments::new()), &ctx)
+        };
+
+        // Outside a repo the git segment is skipped without leaving two separators
+        let output = render(&make_vars(&[("hostname", "laptop"), ("cwd", "~")]));
+        assert_eq!(
+            output,
+            concat!(
+                "\x1b[30;44m laptop \x1b[0m",
+                "\x1b[34;100m>\x1b[0m",
+                "\x1b[37;100m ~ \x1b[0m",
+                "\x1b[90m>\x1b[0m",
+                " $ ",
+            )
+        );
+
+        // Inner styles return to the segment's colors instead of the terminal default
+        let output = render(&make_vars(&[
+            ("hostname", "laptop"),
+            ("git_branch", "main"),
+            ("git_status_unstaged", ":+3"),
+            ("cwd", "~"),
+        ]));
+        assert!(output.contains("\x1b[30;43m main \x1b[31;43m:+3\x1b[0m\x1b[30;43m \x1b[0m"));
+        assert!(output.contains("\x1b[34;43m>\x1b[0m"));
+        assert!(output.contains("\x1b[33;100m>\x1b[0m"));
+    }
+
+    #[test]
+    fn test_powerline_segments_shell_wrapping() {
+        use crate::shell::{BashFormatter, TcshFormatter, ZshFormatter};
+
+        let config = segment_config();
+        let segments: Vec<Template> = config.prompt.segments.iter().map(|s| parse_template(&s.format, &Fragments::new())).collect();
+        let vars = make_vars(&[("hostname", "laptop"), ("git_branch", "main"), ("git_status_unstaged", ":+3")]);
+        let render = |formatter: &dyn ShellFormatter| {
+            let ctx = RenderContext {
+                variables: &vars,
+                config: &config,
+                rule_styles: &HashMap::new(),
+                base: &[],
+                formatter,
+                color_depth: ColorDepth::Ansi16,
+                links: &HashMap::new(),
+            };
+            formatter.finalize(&render_prompt(&segments, &parse_template("", &Fragments::new()), &ctx))
+        };
+
+        // Every escape sequence is wrapped, so only the text and separators are visible
+        let visible = " laptop > main :+3 >";
+        for formatter in [&ZshFormatter as &dyn ShellFormatter, &TcshFormatter, &BashFormatter] {
+            let output = render(formatter);
+            assert_eq!(visible_length(&output, formatter), visible.chars().count(), "{:?}", output);
+        }
+        let bash = render(&BashFormatter);
+        assert_eq!(Regex::new(r"\\\[.*?\\\]").unwrap().replace_all(&bash, ""), visible);
+        assert_eq!(strip_ansi_codes(&render(&RawFormatter)), visible);
+        assert!(render(&BashFormatter).starts_with("\\[\x1b[30;44m\\] laptop \\[\x1b[0m\\]"));
+        assert!(render(&TcshFormatter).starts_with("%{\x1b[30;44m%} laptop %{\x1b[0m%}"));
+    }
+
+    #[test]
+    fn test_validate_segments() {
+        let mut config = segment_config();
+        assert_eq!(validate_segments(&config), Ok(3));
+
+        config.prompt.segments[1].bg = Some("bold".to_string());
+        assert!(validate_segments(&config).unwrap_err().starts_with("[[prompt.segments]] #2: bg:"));
+
+        config.prompt.segments[1].bg = None;
+        config.prompt.segments[2].format = "{cwd|bogus}".to_string();
+        assert!(validate_segments(&config).unwrap_err().contains("Unknown filter"));
+    }
+
+    #[test]
+    fn test_render_first_fitting() {
+        let config = Config::default();
+        let vars = make_vars(&[("hostname", "laptop"), ("ip_address", "10.0.0.7"), ("cwd", "~/src/twig")]);
+        let ctx = RenderContext {
+            variables: &vars,
+            config: &config,
+            rule_styles: &HashMap::new(),
+            base: &[],
+            formatter: &RawFormatter,
+            color_depth: ColorDepth::Ansi16,
+            links: &HashMap::new(),
+        };
+        let templates: Vec<Template> = [
+            "{hostname:green} {ip_address} {cwd} $ ", // 29 columns
+            "{hostname:green} {cwd} $ ",              // 20 columns
+            "{cwd} $ ",                               // 13 columns
+        ]
+        .iter()
+        .map(|format| parse_template(format, &Fragments::new()))
+        .collect();
+        let pick = |width: u16, padding: i32| render_first_fitting(&[], &templates, &ctx, width, padding);
+
+        assert_eq!(pick(40, 5), (0, "\x1b[32mlaptop\x1b[0m 10.0.0.7 ~/src/twig $ ".to_string()));
+        // Steps down one format at a time
+        assert_eq!(pick(30, 5).0, 1);
+        assert_eq!(pick(25, 5).0, 1);
+        assert_eq!(pick(24, 5), (2, "~/src/twig $ ".to_string()));
+        // Nothing fits: the most compact format
+        assert_eq!(pick(10, 5).0, 2);
+        // Negative padding allows overflow
+        assert_eq!(pick(28, -3).0, 0);
+    }
+
+    #[test]
+    fn test_priorities_drop_to_fit() {
+        let config = Config::default();
+        let vars = make_vars(&[
+            ("hostname", "laptop"),
+            ("ip_address", "10.0.0.7"),
+            ("battery_percentage", "80%"),
+            ("cwd", "~/src"),
+        ]);
+        let ctx = RenderContext {
+            variables: &vars,
+            config: &config,
+            rule_styles: &HashMap::new(),
+            base: &[],
+            formatter: &RawFormatter,
+            color_depth: ColorDepth::Ansi16,
+            links: &HashMap::new(),
+        };
+        let template = parse_template(
+            "{hostname!high}~{ip_address!low}[ ({battery_percentage})]!low {cwd} $",
+            &Fragments::new(),
+        );
+        let fit = |width: u16| render_first_fitting(&[], std::slice::from_ref(&template), &ctx, width, 0).1;
+
+        assert_eq!(fit(40), "laptop 10.0.0.7 (80%) ~/src $");
+        // Lowest first, rightmost of equals first; spaces and groups collapse
+        assert_eq!(fit(25), "laptop 10.0.0.7 ~/src $");
+        assert_eq!(fit(16), "laptop ~/src $");
+        assert_eq!(fit(10), " ~/src $");
+        // Unprioritized parts are never dropped
+        assert_eq!(fit(4), " ~/src $");
+
+        // A format is shrunk before the next one is tried
+        let templates = [template.clone(), parse_template("{cwd} $", &Fragments::new())];
+        assert_eq!(render_first_fitting(&[], &templates, &ctx, 16, 0), (0, "laptop ~/src $".to_string()));
+        assert_eq!(render_first_fitting(&[], &templates, &ctx, 7, 0), (1, "~/src $".to_string()));
+    }
+
+    #[test]
+    fn test_group_priorities() {
+        let config = Config::default();
+        let vars = make_vars(&[("cwd", "~/src"), ("git_branch", "main"), ("time", "12:00")]);
+        let ctx = RenderContext {
+            variables: &vars,
+            config: &config,
+            rule_styles: &HashMap::new(),
+            base: &[],
+            formatter: &RawFormatter,
+            color_depth: ColorDepth::Ansi16,
+            links: &HashMap::new(),
+        };
+        let fit = |format: &str, width: u16| {
+            let template = parse_template(format, &Fragments::new());
+            render_first_fitting(&[], std::slice::from_ref(&template), &ctx, width, 0).1
+        };
+
+        // A group with a priority is dropped as a whole
+        assert_eq!(fit("{cwd}[ on {git_branch}]!low $", 20), "~/src on main $");
+        assert_eq!(fit("{cwd}[ on {git_branch}]!low $", 12), "~/src $");
+
+        // A higher-priority group stays while lower parts go
+        assert_eq!(fit("{time!low} {cwd}[ on {git_branch}]!high $", 16), " ~/src on main $");
+    }
+
+    #[test]
+    fn test_segment_priorities() {
+        let mut config = segment_config();
+        config.prompt.segments[0].priority = Some(1);
+        config.prompt.segments[1].format = " {git_branch!2} {git_status_unstaged:red} ".to_string();
+        let segments: Vec<Template> = config.prompt.segments.iter().map(|s| parse_template(&s.format, &Fragments::new())).collect();
+        let vars = make_vars(&[("hostname", "laptop"), ("git_branch", "main"), ("cwd", "~")]);
+        let ctx = RenderContext {
+            variables: &vars,
+            config: &config,
+            rule_styles: &HashMap::new(),
+            base: &[],
+            formatter: &RawFormatter,
+            color_depth: ColorDepth::Ansi16,
+            links: &HashMap::new(),
+        };
+        let template = parse_template(&config.prompt.format, &Fragments::new());
+        let fit = |width: u16| {
+            strip_ansi_codes(&render_first_fitting(&segments, std::slice::from_ref(&template), &ctx, width, 0).1)
+        };
+
+        assert_eq!(fit(30), " laptop > main  > ~ > $ ");
+        // The hostname segment goes as a whole
+        assert_eq!(fit(20), " main  > ~ > $ ");
+        // Then parts inside segments; a segment left without a value is skipped
+        assert_eq!(fit(10), " ~ > $ ");
+    }
+
+    #[test]
+    fn test_validate_formats() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[prompt.formats]]
+            format = "{hostname:green} {cwd}"
+            min_width = 100
+
+            [[prompt.formats]]
+            format = "{cwd|basename}"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(validate_formats(&config), Ok(2));
+
+        config.prompt.formats[1].format = "{cwd:notacolor}".to_string();
+        assert!(validate_formats(&config).unwrap_err().starts_with("[[prompt.formats]] #2:"));
+
+        config.prompt.formats[1].format = "{cwd}".to_string();
+        config.prompt.formats[0].max_width = Some(80);
+        assert_eq!(
+            validate_formats(&config).unwrap_err(),
+            "[[prompt.formats]] #1: min_width (100) is greater than max_width (80)"
+        );
+    }
+
+    #[test]
+    fn test_right_prompt_placement() {
+        let left = "\x1b[32m/home/me/src\x1b[0m\n$ ";
+        assert_eq!(last_line_length(left, &RawFormatter), 2);
+        assert_eq!(last_line_length("$ ", &RawFormatter), 2);
+
+        // Only the last line is shared with the right prompt
+        assert!(right_prompt_fits(left, "\x1b[2m12:00:00\x1b[0m", 20, 5, &RawFormatter));
+        assert!(!right_prompt_fits(left, "\x1b[2m12:00:00\x1b[0m", 20, 10, &RawFormatter));
+        assert!(!right_prompt_fits("/home/me/src/twig $ ", "12:00:00", 30, 5, &RawFormatter));
+        assert!(right_prompt_fits("/home/me/src/twig $ ", "12:00:00", 30, -2, &RawFormatter));
+
+        let right = RawFormatter.position_right("12:00:00", 73);
+        assert_eq!(right, "\x1b[s\x1b[73G12:00:00\x1b[u");
+        assert_eq!(insert_at_last_line(left, &right), format!("\x1b[32m/home/me/src\x1b[0m\n{}$ ", right));
+        assert_eq!(insert_at_last_line("$ ", "R"), "R$ ");
+    }
+
+    #[test]
+    fn test_fill_pads_line_to_width() {
+        use crate::shell::{BashFormatter, ZshFormatter};
+
+        let vars = make_vars(&[("cwd", "~/src"), ("time", "12:00")]);
+        let render = |template: &str, formatter: &dyn ShellFormatter, width: Option<u16>| {
+            resolve_fills(&render_with(template, &vars, formatter, ColorDepth::Ansi16), width, 0, formatter)
+        };
+
+        assert_eq!(render("{cwd} {fill:\"─\"} {time}", &RawFormatter, Some(20)), "~/src ──────── 12:00");
+        // Styled fills keep their color; wrappers don't count towards the width
+        assert_eq!(
+            render("{cwd:green}{fill:\"-\":bright_black}{time}", &ZshFormatter, Some(12)),
+            "%{\x1b[32m%}~/src%{\x1b[0m%}%{\x1b[90m%}--%{\x1b[0m%}12:00"
+        );
+        assert_eq!(
+            render("{cwd:green}{fill:\"-\"}{time}", &BashFormatter, Some(12)),
+            "\\[\x1b[32m\\]~/src\\[\x1b[0m\\]--12:00"
+        );
+        // Multi-character fill text is repeated and cut to fit
+        assert_eq!(render("{fill:\"=-\"}|", &RawFormatter, Some(6)), "=-=-=|");
+        // Several fills share the free columns
+        assert_eq!(render("a{fill:\"-\"}b{fill:\".\"}c", &RawFormatter, Some(8)), "a---b..c");
+        // Unknown width: nothing
+        assert_eq!(render("{cwd}{fill:\"─\"}{time}", &RawFormatter, None), "~/src12:00");
+        // Fill text is escaped after it has been measured
+        assert_eq!(render("{cwd}{fill:\"%\"}$", &ZshFormatter, Some(8)), "~/src%%%%$");
+        // Widths are in columns: wide content leaves less room, and a wide
+        // fill character that doesn't fit is padded with a space
+        let vars = make_vars(&[("cwd", "~/文档")]);
+        let render = |template: &str, width: u16| {
+            resolve_fills(&render_with(template, &vars, &RawFormatter, ColorDepth::Ansi16), Some(width), 0, &RawFormatter)
+        };
+        assert_eq!(render("{cwd}{fill:\"-\"}|", 10), "~/文档---|");
+        assert_eq!(render("{cwd}{fill:\"字\"}|", 10), "~/文档字 |");
+    }
+
+    #[test]
+    fn test_fill_multi_line() {
+        let vars = make_vars(&[("cwd", "~/src"), ("time", "12:00")]);
+        let output = render_with("{cwd}{fill:\"─\"}{time}\n{fill:\"-\"}$ ", &vars, &RawFormatter, ColorDepth::Ansi16);
+        // Fills take no space until expanded, so responsive switching measures the content
+        assert_eq!(visible_length(&output, &RawFormatter), 10);
+        // Each line is padded on its own; the last line leaves room for a right prompt
+        assert_eq!(resolve_fills(&output, Some(14), 0, &RawFormatter), "~/src────12:00\n------------$ ");
+        assert_eq!(resolve_fills(&output, Some(14), 6, &RawFormatter), "~/src────12:00\n------$ ");
+        // Content wider than the terminal: empty fill
+        assert_eq!(resolve_fills(&output, Some(8), 0, &RawFormatter), "~/src12:00\n------$ ");
+    }
+
+    #[test]
+    fn test_secondary_formats() {
+        use crate::shell::ZshFormatter;
+
+        let config: Config = toml::from_str(
+            r#"
+            [prompt]
+            format = "{cwd} $ "
+            continuation = '{"…":bright_black} '
+            "#,
+        )
+        .unwrap();
+        assert_eq!(secondary_format(&config.prompt, "continuation"), Ok("{\"…\":bright_black} "));
+        // Unset kinds fall back to the shell defaults
+        assert_eq!(secondary_format(&config.prompt, "trace"), Ok("+ "));
+        assert_eq!(secondary_format(&config.prompt, "select"), Ok("#? "));
+        assert!(secondary_format(&config.prompt, "primary").unwrap_err().contains("Unknown prompt kind"));
+
+        // Same engine and shell wrapping as the primary prompt
+        let vars = HashMap::new();
+        assert_eq!(
+            render_with(secondary_format(&config.prompt, "continuation").unwrap(), &vars, &ZshFormatter, ColorDepth::Ansi16),
+            "%{\x1b[90m%}…%{\x1b[0m%} "
+        );
+    }
+
+    #[test]
+    fn test_compact_format() {
+        let config: Config = toml::from_str("[prompt]\nformat = \"{cwd} $ \"\nformat_transient = \"❯ \"\n").unwrap();
+        assert_eq!(compact_format(&config.prompt, None, true), Ok(Some("❯ ")));
+        assert_eq!(compact_format(&config.prompt, None, false), Ok(None));
+        // A secondary kind wins over --transient
+        assert_eq!(compact_format(&config.prompt, Some("trace"), true), Ok(Some("+ ")));
+        assert!(compact_format(&config.prompt, Some("primary"), false).is_err());
+
+        // Without format_transient, --transient renders the full prompt
+        let config: Config = toml::from_str("[prompt]\nformat = \"{cwd} $ \"\n").unwrap();
+        assert_eq!(compact_format(&config.prompt, None, true), Ok(None));
+    }
+
+    #[test]
+    fn test_escapes() {
+        let vars = make_vars(&[("cwd", "/tmp"), ("git_branch", "main")]);
+        let render = |template: &str| render_with(template, &vars, &RawFormatter, ColorDepth::Ansi16);
+
+        // JSON-like decorations around variables
+        assert_eq!(render(r#"\{"dir": "{cwd}"\}"#), r#"{"dir": "/tmp"}"#);
+        assert_eq!(render(r#"{"\"quoted\"":red}"#), "\x1b[31m\"quoted\"\x1b[0m");
+        assert_eq!(render(r"{cwd}\t\u{2192}\t{git_branch}"), "/tmp\t\u{2192}\tmain");
+        assert_eq!(render(r"\\{cwd}"), "\\/tmp");
+
+        // Escaped braces are not placeholders, so they don't pull in providers
+        let template = Template::parse_with(r"\{git_branch\} {cwd}", &Fragments::new()).unwrap();
+        assert_eq!(template.variables(), vec!["cwd"]);
+
+        let err = validate_format_syntax(r"{cwd} \u{zz}", &Fragments::new()).unwrap_err();
+        assert!(err.starts_with("invalid unicode escape"));
+        assert!(err.ends_with("at line 1, column 7"));
+    }
+
+    fn fragment_config() -> Config {
+        toml::from_str(
+            r#"
+            [fragments]
+            status = "{git_status_clean:green}{git_status_unstaged:red}"
+            git = "{git_branch:magenta}{@status}"
+
+            [prompt]
+            format = "{cwd:green}[ {@git}] $ "
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_fragments() {
+        let config = fragment_config();
+        let template = parse_template(&config.prompt.format, &config.fragments);
+        // Fragment variables count toward provider discovery and implicit sections
+        assert_eq!(template.variables(), vec!["cwd", "git_branch", "git_status_clean", "git_status_unstaged"]);
+        let registry = providers::ProviderRegistry::new();
+        assert!(registry.determine_providers(&template.variables()).contains(&"git"));
+
+        let vars = make_vars(&[("cwd", "~"), ("git_branch", "main"), ("git_status_unstaged", ":+1")]);
+        let ctx = RenderContext {
+            variables: &vars,
+            config: &config,
+            rule_styles: &HashMap::new(),
+            base: &[],
+            formatter: &RawFormatter,
+            color_depth: ColorDepth::Ansi16,
+            links: &HashMap::new(),
+        };
+        assert_eq!(
+            render_template(&template, &ctx),
+            "\x1b[32m~\x1b[0m \x1b[35mmain\x1b[0m\x1b[32m\x1b[0m\x1b[31m:+1\x1b[0m $ "
+        );
+        // Fragments inside a group collapse with it
+        let vars = make_vars(&[("cwd", "~")]);
+        assert_eq!(render_template(&template, &RenderContext { variables: &vars, ..ctx }), "\x1b[32m~\x1b[0m $ ");
+    }
+
+    #[test]
+    fn test_fragment_conditional_space() {
+        let fragments: Fragments = [("a", "{cwd}~"), ("b", "~{@c}"), ("c", " on {git_branch}"), ("host", "{hostname}")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let vars = make_vars(&[("cwd", "/root/crate"), ("hostname", "vm"), ("git_branch", "main")]);
+        let config = Config::default();
+        let ctx = RenderContext {
+            variables: &vars,
+            config: &config,
+            rule_styles: &HashMap::new(),
+            base: &[],
+            formatter: &RawFormatter,
+            color_depth: ColorDepth::Ansi16,
+            links: &HashMap::new(),
+        };
+        let render = |format: &str| render_template(&parse_template(format, &fragments), &ctx);
+
+        // A reference renders like its text written in place
+        for (format, inline) in [
+            ("{@a}{hostname}", "{cwd}~{hostname}"),
+            ("{@a}{@host}", "{cwd}~{hostname}"),
+            ("{@a} $", "{cwd}~ $"),
+            ("{cwd}{@b}", "{cwd}~ on {git_branch}"),
+            ("{cwd}~{@host}", "{cwd}~{hostname}"),
+        ] {
+            assert_eq!(render(format), render(inline), "{}", format);
+        }
+        assert_eq!(render("{@a}{hostname}"), "/root/crate vm");
+    }
+
+    #[test]
+    fn test_validate_fragments() {
+        let mut config = fragment_config();
+        assert_eq!(validate_fragments(&config), Ok(2));
+        assert!(validate_format_syntax("{@git} {@nope}", &config.fragments).unwrap_err().contains("unknown fragment '@nope'"));
+
+        // Cycles are reported even when no format uses the fragments
+        config.fragments.insert("a".to_string(), "{@b}".to_string());
+        config.fragments.insert("b".to_string(), "x{@a}".to_string());
+        let err = validate_fragments(&config).unwrap_err();
+        assert!(err.starts_with("[fragments] a: fragment cycle: @a -> @b -> @a"), "{}", err);
+
+        let mut config = fragment_config();
+        config.fragments.insert("bad".to_string(), "{cwd:purple}".to_string());
+        assert!(validate_fragments(&config).unwrap_err().starts_with("[fragments] bad:"));
+    }
+
+    #[test]
+    fn test_validate_palette() {
+        let palette: Palette = [("warn", "red,bold"), ("alert", "warn,underline")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(validate_palette(&palette), Ok(2));
+        assert_eq!(validate_colors_and_styles("{cwd:alert} {time:bg:blue}", &palette, &Fragments::new()), Ok(2));
+        assert!(validate_colors_and_styles("{cwd:bg:warn}", &palette, &Fragments::new()).unwrap_err().contains("background"));
+
+        let palette: Palette = [("warn", "purple")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let err = validate_palette(&palette).unwrap_err();
+        assert!(err.starts_with("[styles] warn:"));
+        assert!(err.contains("purple"));
+    }
+
+    #[test]
+    fn test_default_config_serializes_without_empty_tables() {
+        let toml_string = toml::to_string_pretty(&create_default_config()).unwrap();
+        assert!(!toml_string.contains("styles"));
+        assert!(!toml_string.contains("rules"));
+        assert!(!toml_string.contains("theme"));
+        assert!(!toml_string.contains("format_right"));
+        assert!(!toml_string.contains("format_transient"));
+        assert!(!toml_string.contains("continuation"));
+    }
+
+    #[test]
+    fn test_validate_value_styles() {
+        let config: Config = toml::from_str(
+            r#"
+            [git]
+            styles = { main = "green,bold", "release/*" = "rgb(1,2,3)" }
+
+            [prompt]
+            format = "{git_branch}"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(validate_value_styles(&config), Ok(2));
+
+        let config: Config = toml::from_str(
+            r#"
+            [hostname]
+            styles = { skyfall = "purple" }
+
+            [prompt]
+            format = "{hostname}"
+            "#,
+        )
+        .unwrap();
+        let err = validate_value_styles(&config).unwrap_err();
+        assert!(err.contains("[hostname] styles.\"skyfall\""));
+        assert!(err.contains("purple"));
+    }
+
+    #[test]
+    fn test_validate_colors_and_styles_background_and_attributes() {
+        let format = "{cwd:bg:blue,white} {time:on_bright_black,dim,blink,reverse,strikethrough,overline}";
+        assert_eq!(validate_colors_and_styles(format, &Palette::new(), &Fragments::new()), Ok(8));
+
+        let result = validate_colors_and_styles("{cwd:bg:bold}", &Palette::new(), &Fragments::new());
+        assert!(result.unwrap_err().contains("bg:bold"));
+    }
+
+    #[test]
+    fn test_color_downsampling() {
+        let vars = make_vars(&[("time", "10:00")]);
+        let formatter = RawFormatter;
+        let config = Config::default();
+        let template = parse_template("{time:rgb(255,0,0)}", &Fragments::new());
+        let render = |color_depth| {
+            render_template(&template, &RenderContext { variables: &vars, config: &config, rule_styles: &HashMap::new(), base: &[], formatter: &formatter, color_depth, links: &HashMap::new() })
+        };
+
+        assert_eq!(render(ColorDepth::TrueColor), "\x1b[38;2;255;0;0m10:00\x1b[0m");
+        assert_eq!(render(ColorDepth::Palette256), "\x1b[38;5;196m10:00\x1b[0m");
+        assert_eq!(render(ColorDepth::Ansi16), "\x1b[91m10:00\x1b[0m");
+    }
+
     #[test]
     fn test_validate_time_format_valid() {
         assert!(validate_time_format("%H:%M:%S"));
@@ -1372,34 +2965,6 @@ mod tests {
         assert!(result.contains("main"));
     }
 
-    #[test]
-    fn test_extract_all_variables() {
-        // Test basic variable extraction
-        let template = "{cwd} {git_branch}";
-        let vars = extract_all_variables(template);
-        assert_eq!(vars, vec!["cwd", "git_branch"]);
-
-        // Test with colors
-        let template = "{cwd:green} {git_branch:magenta}";
-        let vars = extract_all_variables(template);
-        assert_eq!(vars, vec!["cwd", "git_branch"]);
-
-        // Test with literals (should be excluded)
-        let template = "{cwd} {\"!\": bold}";
-        let vars = extract_all_variables(template);
-        assert_eq!(vars, vec!["cwd"]);
-
-        // Test with environment variables (should be excluded)
-        let template = "{cwd} {$USER}";
-        let vars = extract_all_variables(template);
-        assert_eq!(vars, vec!["cwd"]);
-
-        // Test complex prompt
-        let template = "{time:cyan} {hostname:yellow} {cwd:green}~{git_branch:magenta}{git_status_clean:green}";
-        let vars = extract_all_variables(template);
-        assert_eq!(vars, vec!["time", "hostname", "cwd", "git_branch", "git_status_clean"]);
-    }
-
     #[test]
     fn test_selective_provider_execution() {
         use crate::providers::ProviderRegistry;
@@ -1430,40 +2995,171 @@ mod tests {
         let vars = vec!["battery_percentage", "battery_power"];
         let providers = registry.determine_providers(&vars);
         assert!(providers.contains(&"battery"));
+
+        // The last command's variables all come from one provider
+        let vars = vec!["exit_code", "cmd_duration", "jobs", "vi_mode"];
+        assert_eq!(registry.determine_providers(&vars), vec!["status"]);
+    }
+
+    #[test]
+    fn test_status_sections_are_implicit() {
+        let mut config = Config::default();
+        apply_implicit_sections(&mut config, &parse_template("{exit_code}~{jobs} $", &Fragments::new()));
+        assert!(config.exit.is_some());
+        assert!(config.cmd.is_some());
+        assert!(config.vi.is_some());
     }
 
     #[test]
     fn test_visible_length_single_line() {
         // Test simple single-line prompt
         let prompt = "user@host:~/path$ ";
-        assert_eq!(visible_length(prompt), 18);
+        assert_eq!(visible_length(prompt, &RawFormatter), 18);
 
         // Test with ANSI codes
         let prompt_with_ansi = "\x1b[32muser@host\x1b[0m:~/path$ ";
-        assert_eq!(visible_length(prompt_with_ansi), 18);
+        assert_eq!(visible_length(prompt_with_ansi, &RawFormatter), 18);
 
         // Test with tcsh wrappers
         let prompt_tcsh = "%{\x1b[32m%}user@host%{\x1b[0m%}:~/path$ ";
-        assert_eq!(visible_length(prompt_tcsh), 18);
+        assert_eq!(visible_length(prompt_tcsh, &crate::shell::TcshFormatter), 18);
+    }
+
+    #[test]
+    fn test_visible_length_per_shell() {
+        use crate::shell::{BashFormatter, TcshFormatter, ZshFormatter};
+
+        // Each shell only strips its own wrappers; other shells' syntax is text
+        let vars = make_vars(&[("cwd", "/tmp/\\[x\\]%{y%}")]);
+        for formatter in [&RawFormatter as &dyn ShellFormatter, &ZshFormatter, &BashFormatter, &TcshFormatter] {
+            let output = formatter.finalize(&substitute_variables("{cwd:green}", &vars, formatter));
+            assert_eq!(visible_length(&output, formatter), "/tmp/\\[x\\]%{y%}".len(), "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn test_visible_length_counts_columns() {
+        // CJK takes two columns, combining marks and joiners none
+        assert_eq!(visible_length("\x1b[34m~/プロジェクト\x1b[0m $ ", &RawFormatter), 17);
+        assert_eq!(visible_length("cafe\u{301}", &RawFormatter), 4);
+        assert_eq!(visible_length("✔ ok", &RawFormatter), 4);
+        assert_eq!(visible_length("👩\u{200d}💻 dev", &RawFormatter), 6);
+    }
+
+    #[test]
+    fn test_hyperlinks() {
+        use crate::shell::{BashFormatter, ZshFormatter};
+
+        let config = Config::default();
+        let vars = make_vars(&[("cwd", "/tmp/a b"), ("git_branch", "main")]);
+        let links = make_vars(&[("cwd", "file://laptop/tmp/a%20b")]);
+        let render = |template: &str, formatter: &dyn ShellFormatter| {
+            let ctx = RenderContext {
+                variables: &vars,
+                config: &config,
+                rule_styles: &HashMap::new(),
+                base: &[],
+                formatter,
+                color_depth: ColorDepth::Ansi16,
+                links: &links,
+            };
+            render_template(&parse_template(template, &config.fragments), &ctx)
+        };
+
+        assert_eq!(
+            render("{cwd:cyan,link} $", &RawFormatter),
+            "\x1b]8;;file://laptop/tmp/a%20b\x07\x1b[36m/tmp/a b\x1b[0m\x1b]8;;\x07 $"
+        );
+        // Only values styled `link` that have a target are linked
+        assert_eq!(render("{cwd}", &RawFormatter), "/tmp/a b");
+        assert_eq!(render("{git_branch:link}", &RawFormatter), "main");
+
+        // The link sequences are zero-width in every shell
+        for formatter in [&RawFormatter as &dyn ShellFormatter, &ZshFormatter, &BashFormatter] {
+            let output = formatter.finalize(&render("{cwd:link} $", formatter));
+            assert_eq!(visible_length(&output, formatter), "/tmp/a b $".len(), "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn test_template_text_is_not_escaped() {
+        use crate::shell::{BashFormatter, ZshFormatter};
+
+        let vars = make_vars(&[("cwd", "/root/crate")]);
+        assert_eq!(substitute_variables("%n@%m {cwd} %# ", &vars, &ZshFormatter), "%n@%m /root/crate %# ");
+        assert_eq!(substitute_variables("\\u@\\h:\\w\\$ ", &vars, &BashFormatter), "\\u@\\h:\\w\\$ ");
+    }
+
+    #[test]
+    fn test_tcsh_template_text_stays_literal() {
+        use crate::shell::TcshFormatter;
+
+        let vars = make_vars(&[("cwd", "/root/crate")]);
+        let output = TcshFormatter.finalize(&substitute_variables("{cwd} 100% done! > ", &vars, &TcshFormatter));
+        assert_eq!(output, "/root/crate 100%% done\\! > ");
+        assert_eq!(visible_length(&output, &TcshFormatter), "/root/crate 100% done! > ".len());
+    }
+
+    #[test]
+    fn test_values_cannot_inject_prompt_sequences() {
+        use crate::shell::{BashFormatter, TcshFormatter, ZshFormatter};
+
+        let vars = make_vars(&[("cwd", "/tmp/%{x$(id)`id`\\u!^%n")]);
+        let render = |formatter: &dyn ShellFormatter| {
+            formatter.finalize(&substitute_variables("{cwd:green} {\"100%\"} $ ", &vars, formatter))
+        };
+
+        assert_eq!(
+            render(&ZshFormatter),
+            "%{\x1b[32m%}/tmp/%%{x\\$(id)\\`id\\`\\\\u!!^%%n%{\x1b[0m%} 100%% $ "
+        );
+        assert_eq!(
+            render(&BashFormatter),
+            "\\[\x1b[32m\\]/tmp/%{x\\\\$(id)\\\\`id\\\\`\\\\\\\\u!^%n\\[\x1b[0m\\] 100% $ "
+        );
+        assert_eq!(
+            render(&TcshFormatter),
+            "%{\x1b[32m%}/tmp/%%{x$(id)`id`\\\\u\\!\\^%%n%{\x1b[0m%} 100%% $ "
+        );
+
+        // Escaping doesn't change the measured width
+        for formatter in [&ZshFormatter as &dyn ShellFormatter, &BashFormatter, &TcshFormatter] {
+            assert_eq!(visible_length(&render(formatter), formatter), "/tmp/%{x$(id)`id`\\u!^%n 100% $ ".len());
+        }
+    }
+
+    #[test]
+    fn test_hyperlinks_disabled() {
+        let config: Config = toml::from_str("[prompt]\nformat = \"{cwd:link}\"\nhyperlinks = false\n").unwrap();
+        assert!(!config.prompt.hyperlinks_enabled());
+        assert!(Config::default().prompt.hyperlinks_enabled());
+
+        // Providers report no targets, so `link` renders plain text
+        let builtin = providers::builtin::BuiltinProvider::new();
+        let mut config = config;
+        config.add_implicit_section("cwd".to_string(), serde_json::json!({}));
+        let output = providers::Provider::collect(&builtin, &config, false).unwrap();
+        assert!(output.links.is_empty());
+        assert!(output.variables.contains_key("cwd"));
     }
 
     #[test]
     fn test_visible_length_multiline() {
         // Test multi-line prompt - should return longest line
         let multiline = "first line is very long (30 chars)\nsecond";
-        assert_eq!(visible_length(multiline), 34); // First line is longer
+        assert_eq!(visible_length(multiline, &RawFormatter), 34); // First line is longer
 
         let multiline2 = "short\nthis is the much longer second line";
-        assert_eq!(visible_length(multiline2), 35); // Second line is longer
+        assert_eq!(visible_length(multiline2, &RawFormatter), 35); // Second line is longer
 
         // Test with ANSI codes in multi-line
         let multiline_ansi = "\x1b[32mfirst line is very long\x1b[0m\n\x1b[33mshort\x1b[0m";
-        assert_eq!(visible_length(multiline_ansi), 23); // First line without ANSI
+        assert_eq!(visible_length(multiline_ansi, &RawFormatter), 23); // First line without ANSI
 
         // Test realistic prompt (like user's config)
         let realistic = "--(time host /very/long/path git)--\n(width:155) ! ";
         let first_line_len = "--(time host /very/long/path git)--".len();
         let second_line_len = "(width:155) ! ".len();
-        assert_eq!(visible_length(realistic), first_line_len.max(second_line_len));
+        assert_eq!(visible_length(realistic, &RawFormatter), first_line_len.max(second_line_len));
     }
 }
@@ -0,0 +1,181 @@
+// twig/src/powerline.rs
+
+//! Powerline-style segment bar
+//!
+//! ```toml
+//! [prompt]
+//! separator = "powerline"   # "round", "ascii", or any string
+//!
+//! [[prompt.segments]]
+//! format = " {hostname} "
+//! fg = "black"
+//! bg = "blue"
+//!
+//! [[prompt.segments]]
+//! format = " {git_branch} "
+//! fg = "black"
+//! bg = "magenta"
+//! ```
+//!
+//! Segments are rendered by the normal template renderer (see `main.rs`),
+//! then joined here: each separator glyph takes the left segment's background
+//! as its foreground and the right segment's background as its background, so
+//! the colors flow into each other. Between segments sharing a background the
+//! thin separator is used instead. Every color change is a separate
+//! `format_ansi` call, so the bar is wrapped correctly for every shell, and
+//! the glyphs are escaped like any other text (a custom `separator = "%"`
+//! shows as `%` in zsh).
+
+use crate::shell::ShellFormatter;
+use crate::style::{ColorDepth, Style};
+
+/// Separator glyphs: `hard` between different backgrounds, `thin` between equal ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Separator {
+    pub hard: String,
+    pub thin: String,
+}
+
+impl Separator {
+    /// Separator for the `separator` config value
+    ///
+    /// - `"powerline"` (default): `\u{e0b0}` / `\u{e0b1}` (needs a Nerd/Powerline font)
+    /// - `"round"`: `\u{e0b4}` / `\u{e0b5}`
+    /// - `"ascii"`: `>` / `|` for terminals without patched fonts
+    /// - anything else is used as-is for both
+    pub fn from_config(name: Option<&str>) -> Self {
+        let (hard, thin) = match name.unwrap_or("powerline") {
+            "powerline" => ("\u{e0b0}", "\u{e0b1}"),
+            "round" => ("\u{e0b4}", "\u{e0b5}"),
+            "ascii" => (">", "|"),
+            custom => (custom, custom),
+        };
+        Self {
+            hard: hard.to_string(),
+            thin: thin.to_string(),
+        }
+    }
+}
+
+/// A rendered segment: its text (inner styles already applied) and base style
+pub struct Segment {
+    pub text: String,
+    pub style: Style,
+}
+
+/// Join visible segments into a bar with colored separators
+///
+/// The bar ends with a separator leading from the last background back to
+/// the terminal's default.
+pub fn join(
+    segments: &[Segment],
+    separator: &Separator,
+    formatter: &dyn ShellFormatter,
+    depth: ColorDepth,
+) -> String {
+    let mut output = String::new();
+
+    for (i, segment) in segments.iter().enumerate() {
+        output.push_str(&paint(&segment.text, &segment.style, formatter, depth));
+
+        let next_bg = segments.get(i + 1).map(|next| next.style.bg);
+        let (glyph, style) = match next_bg {
+            // Same background on both sides: thin separator in the segment's foreground
+            Some(bg) if bg.is_some() && bg == segment.style.bg => (
+                &separator.thin,
+                Style {
+                    fg: segment.style.fg,
+                    bg,
+                    attributes: Vec::new(),
+                },
+            ),
+            // Background transition (or end of the bar)
+            _ => (
+                &separator.hard,
+                Style {
+                    fg: segment.style.bg,
+                    bg: next_bg.flatten(),
+                    attributes: Vec::new(),
+                },
+            ),
+        };
+        output.push_str(&paint(&formatter.escape_text(glyph), &style, formatter, depth));
+    }
+
+    output
+}
+
+/// Wrap text in a style's codes (plain text if the style is empty)
+fn paint(text: &str, style: &Style, formatter: &dyn ShellFormatter, depth: ColorDepth) -> String {
+    match style.sgr_params(depth) {
+        Some(params) => formatter.format_ansi(&format!("\x1b[{}m", params), text, "\x1b[0m"),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::{BashFormatter, RawFormatter, TcshFormatter, ZshFormatter};
+
+    fn segment(text: &str, spec: &[&str]) -> Segment {
+        Segment {
+            text: text.to_string(),
+            style: Style::parse(spec).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_separator_presets() {
+        assert_eq!(Separator::from_config(None).hard, "\u{e0b0}");
+        assert_eq!(Separator::from_config(Some("round")).thin, "\u{e0b5}");
+        assert_eq!(Separator::from_config(Some("ascii")).hard, ">");
+        assert_eq!(Separator::from_config(Some("▶")).thin, "▶");
+    }
+
+    #[test]
+    fn test_color_transitions() {
+        let segments = [segment(" a ", &["black", "bg:blue"]), segment(" b ", &["white", "bg:red"])];
+        let output = join(&segments, &Separator::from_config(Some("ascii")), &RawFormatter, ColorDepth::TrueColor);
+        assert_eq!(
+            output,
+            concat!(
+                "\x1b[30;44m a \x1b[0m",
+                // blue -> red
+                "\x1b[34;41m>\x1b[0m",
+                "\x1b[37;41m b \x1b[0m",
+                // red -> default background
+                "\x1b[31m>\x1b[0m",
+            )
+        );
+    }
+
+    #[test]
+    fn test_thin_separator_on_same_background() {
+        let segments = [segment("a", &["white", "bg:blue"]), segment("b", &["black", "bg:blue"])];
+        let output = join(&segments, &Separator::from_config(None), &RawFormatter, ColorDepth::TrueColor);
+        assert!(output.contains("\x1b[37;44m\u{e0b1}\x1b[0m"));
+        assert!(output.ends_with("\x1b[34m\u{e0b0}\x1b[0m"));
+    }
+
+    #[test]
+    fn test_shell_wrapping() {
+        let segments = [segment("a", &["black", "bg:blue"])];
+        let bash = join(&segments, &Separator::from_config(Some("ascii")), &BashFormatter, ColorDepth::Ansi16);
+        assert_eq!(bash, "\\[\x1b[30;44m\\]a\\[\x1b[0m\\]\\[\x1b[34m\\]>\\[\x1b[0m\\]");
+        let tcsh = join(&segments, &Separator::from_config(Some("ascii")), &TcshFormatter, ColorDepth::Ansi16);
+        assert_eq!(tcsh, "%{\x1b[30;44m%}a%{\x1b[0m%}%{\x1b[34m%}>%{\x1b[0m%}");
+    }
+
+    #[test]
+    fn test_custom_separator_is_escaped() {
+        let segments = [segment("a", &["black", "bg:blue"])];
+        let zsh = join(&segments, &Separator::from_config(Some("%")), &ZshFormatter, ColorDepth::Ansi16);
+        assert!(zsh.ends_with("%{\x1b[34m%}%%%{\x1b[0m%}"));
+    }
+
+    #[test]
+    fn test_no_segments() {
+        assert_eq!(join(&[], &Separator::from_config(None), &RawFormatter, ColorDepth::TrueColor), "");
+    }
+}
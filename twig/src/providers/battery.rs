@@ -1,6 +1,6 @@
 // twig/src/providers/battery.rs
 
-use super::{Provider, ProviderResult};
+use super::{Provider, ProviderOutput, ProviderResult};
 use crate::config::Config;
 use battery::{Manager, State};
 use serde_json::{json, Value};
@@ -15,7 +15,7 @@ impl BatteryProvider {
 
     /// Get battery information
     /// Returns (percentage, status, power) where power is in watts (positive=charging, negative=discharging)
-    fn get_battery_info(&self) -> Option<(u8, String, Option<String>)> {
+    fn get_battery_info(&self) -> Option<(u8, String, Option<f32>)> {
         // Create battery manager
         let manager = Manager::new().ok()?;
 
@@ -47,8 +47,7 @@ impl BatteryProvider {
             }
 
             if watts.abs() > 0.1 {
-                // Format with sign: +45.2W (charging) or -11.7W (discharging)
-                Some(format!("{:+.1}W", watts))
+                Some(watts)
             } else {
                 None
             }
@@ -67,18 +66,24 @@ impl Provider for BatteryProvider {
         vec!["battery"]
     }
 
-    fn collect(&self, _config: &Config, _validate: bool) -> ProviderResult<HashMap<String, String>> {
-        let mut vars = HashMap::new();
+    fn collect(&self, _config: &Config, _validate: bool) -> ProviderResult<ProviderOutput> {
+        let mut output = ProviderOutput::default();
+        let vars = &mut output.variables;
 
         // Get battery info if available
         // Returns empty vars if no battery (common for desktops)
         if let Some((percentage, status, power)) = self.get_battery_info() {
             vars.insert("battery_percentage".to_string(), format!("{}%", percentage));
             vars.insert("battery_status".to_string(), status.clone());
+            output.numbers.insert("battery_percentage".to_string(), percentage as f64);
 
             // Add power draw if available
             // Split into charging/discharging variables for conditional coloring
-            if let Some(power_str) = power {
+            if let Some(watts) = power {
+                output.numbers.insert("battery_power".to_string(), watts as f64);
+
+                // Format with sign: +45.2W (charging) or -11.7W (discharging)
+                let power_str = format!("{:+.1}W", watts);
                 // Keep the generic one for backward compatibility
                 vars.insert("battery_power".to_string(), power_str.clone());
 
@@ -91,7 +96,7 @@ impl Provider for BatteryProvider {
             }
         }
 
-        Ok(vars)
+        Ok(output)
     }
 
     fn default_config(&self) -> HashMap<String, Value> {
@@ -143,14 +148,14 @@ mod tests {
             assert!(percentage <= 100);
 
             // Check status is one of the known states
-            let valid_states = vec!["Charging", "Discharging", "Full", "Empty", "Unknown"];
+            let valid_states = ["Charging", "Discharging", "Full", "Empty", "Unknown"];
             assert!(valid_states.contains(&status.as_str()));
 
-            // If power is present, check format
-            if let Some(power_str) = power {
-                // Should contain 'W' for watts
-                assert!(power_str.contains('W'));
-                // Should start with + or -
+            // If power is present, it is above the noise threshold
+            if let Some(watts) = power {
+                assert!(watts.abs() > 0.1);
+                // Formatted with sign and unit, e.g. +45.2W or -11.7W
+                let power_str = format!("{:+.1}W", watts);
                 assert!(power_str.starts_with('+') || power_str.starts_with('-'));
             }
         }
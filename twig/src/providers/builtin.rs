@@ -1,7 +1,8 @@
 // twig/src/providers/builtin.rs
 
-use super::{Provider, ProviderResult};
+use super::{Provider, ProviderOutput, ProviderResult};
 use crate::config::Config;
+use crate::hyperlink;
 use chrono::Local;
 use gethostname::gethostname;
 use serde_json::{json, Value};
@@ -26,8 +27,9 @@ impl Provider for BuiltinProvider {
         vec!["time", "hostname", "cwd"]
     }
 
-    fn collect(&self, config: &Config, _validate: bool) -> ProviderResult<HashMap<String, String>> {
-        let mut vars = HashMap::new();
+    fn collect(&self, config: &Config, _validate: bool) -> ProviderResult<ProviderOutput> {
+        let mut output = ProviderOutput::default();
+        let vars = &mut output.variables;
 
         // Handle [time] section
         if let Some(time_config) = &config.time {
@@ -55,10 +57,15 @@ impl Provider for BuiltinProvider {
                 .map(|p| p.to_string_lossy().to_string())
                 .unwrap_or_else(|_| "?".to_string());
             let var_name = cwd_config.name.as_deref().unwrap_or("cwd");
-            vars.insert(var_name.to_string(), cwd);
+            if config.prompt.hyperlinks_enabled() {
+                // file:// URLs name the full host, not the short one shown as {hostname}
+                let host = gethostname().to_string_lossy().to_string();
+                output.links.insert(var_name.to_string(), hyperlink::file_url(&host, &cwd));
+            }
+            output.variables.insert(var_name.to_string(), cwd);
         }
 
-        Ok(vars)
+        Ok(output)
     }
 
     fn default_config(&self) -> HashMap<String, Value> {
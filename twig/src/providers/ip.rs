@@ -1,6 +1,6 @@
 // twig/src/providers/ip.rs
 
-use super::{Provider, ProviderError, ProviderResult};
+use super::{Provider, ProviderOutput, ProviderError, ProviderResult};
 use crate::config::Config;
 use get_if_addrs::{get_if_addrs, IfAddr};
 use serde_json::{json, Value};
@@ -97,7 +97,7 @@ impl Provider for IpProvider {
         vec!["ip"]
     }
 
-    fn collect(&self, config: &Config, validate: bool) -> ProviderResult<HashMap<String, String>> {
+    fn collect(&self, config: &Config, validate: bool) -> ProviderResult<ProviderOutput> {
         let mut vars = HashMap::new();
 
         // Read config
@@ -117,7 +117,7 @@ impl Provider for IpProvider {
                         "Failed to get network interfaces".to_string(),
                     ))
                 } else {
-                    Ok(vars) // Silent failure - return empty vars
+                    Ok(vars.into()) // Silent failure - return empty vars
                 };
             }
         };
@@ -135,7 +135,7 @@ impl Provider for IpProvider {
             }
         }
 
-        Ok(vars)
+        Ok(vars.into())
     }
 
     fn default_config(&self) -> HashMap<String, Value> {
@@ -4,6 +4,7 @@ pub mod battery;
 pub mod builtin;
 pub mod git;
 pub mod ip;
+pub mod status;
 
 use crate::config::Config;
 use serde_json::Value;
@@ -34,9 +35,34 @@ pub struct ProviderTiming {
     pub duration: Duration,
 }
 
+/// What a single provider returns from [`Provider::collect`]
+#[derive(Debug, Default)]
+pub struct ProviderOutput {
+    /// Formatted values substituted into the template (e.g. `battery_percentage` = "85%")
+    pub variables: HashMap<String, String>,
+    /// Raw numbers behind formatted variables, keyed by variable name
+    /// (e.g. `battery_percentage` = 85.0). Section `rules` are evaluated against these.
+    pub numbers: HashMap<String, f64>,
+    /// Hyperlink targets for variables styled `link`, keyed by variable name
+    /// (e.g. `cwd` = "file://laptop/home/me")
+    pub links: HashMap<String, String>,
+}
+
+impl From<HashMap<String, String>> for ProviderOutput {
+    fn from(variables: HashMap<String, String>) -> Self {
+        Self {
+            variables,
+            numbers: HashMap::new(),
+            links: HashMap::new(),
+        }
+    }
+}
+
 /// Result of collecting variables from all providers
 pub struct CollectResult {
     pub variables: HashMap<String, String>,
+    pub numbers: HashMap<String, f64>,
+    pub links: HashMap<String, String>,
     pub timings: Vec<ProviderTiming>,
 }
 
@@ -66,7 +92,8 @@ pub trait Provider {
     /// * `validate` - If true, return errors instead of empty strings
     ///
     /// # Returns
-    /// HashMap of variable_name -> value pairs
+    /// Variable name -> formatted value pairs, plus the raw numbers behind
+    /// numeric variables (providers without numbers can return `vars.into()`)
     ///
     /// # Error Handling
     /// - If validate=false: Return empty vars on error (graceful degradation)
@@ -82,7 +109,7 @@ pub trait Provider {
     ///     "git_behind": "0",       // Commits behind remote
     /// }
     /// ```
-    fn collect(&self, config: &Config, validate: bool) -> ProviderResult<HashMap<String, String>>;
+    fn collect(&self, config: &Config, validate: bool) -> ProviderResult<ProviderOutput>;
 
     /// Default config if section is missing but variables are used in template
     ///
@@ -152,6 +179,7 @@ impl ProviderRegistry {
         registry.register(Box::new(git::GitProvider::new()));
         registry.register(Box::new(ip::IpProvider::new()));
         registry.register(Box::new(battery::BatteryProvider::new()));
+        registry.register(Box::new(status::StatusProvider::new()));
 
         registry
     }
@@ -199,18 +227,22 @@ impl ProviderRegistry {
     /// Result with CollectResult containing variables and timing data, or first error encountered
     pub fn collect_all(&self, config: &Config, validate: bool) -> ProviderResult<CollectResult> {
         let mut variables = HashMap::new();
+        let mut numbers = HashMap::new();
+        let mut links = HashMap::new();
         let mut timings = Vec::new();
 
         for provider in self.providers.values() {
             let start = Instant::now();
             match provider.collect(config, validate) {
-                Ok(vars) => {
+                Ok(output) => {
                     let duration = start.elapsed();
                     timings.push(ProviderTiming {
                         name: provider.name().to_string(),
                         duration,
                     });
-                    variables.extend(vars);
+                    variables.extend(output.variables);
+                    numbers.extend(output.numbers);
+                    links.extend(output.links);
                 }
                 Err(e) if validate => return Err(e),
                 Err(_) => {} // Silent failure in non-validate mode
@@ -220,7 +252,7 @@ impl ProviderRegistry {
         // Sort timings by provider name for consistent output
         timings.sort_by(|a, b| a.name.cmp(&b.name));
 
-        Ok(CollectResult { variables, timings })
+        Ok(CollectResult { variables, numbers, links, timings })
     }
 
     /// Collect variables from specific providers only
@@ -242,19 +274,23 @@ impl ProviderRegistry {
         validate: bool,
     ) -> ProviderResult<CollectResult> {
         let mut variables = HashMap::new();
+        let mut numbers = HashMap::new();
+        let mut links = HashMap::new();
         let mut timings = Vec::new();
 
         for name in provider_names {
             if let Some(provider) = self.get(name) {
                 let start = Instant::now();
                 match provider.collect(config, validate) {
-                    Ok(vars) => {
+                    Ok(output) => {
                         let duration = start.elapsed();
                         timings.push(ProviderTiming {
                             name: provider.name().to_string(),
                             duration,
                         });
-                        variables.extend(vars);
+                        variables.extend(output.variables);
+                        numbers.extend(output.numbers);
+                        links.extend(output.links);
                     }
                     Err(e) if validate => return Err(e),
                     Err(_) => {} // Silent failure in non-validate mode
@@ -265,7 +301,7 @@ impl ProviderRegistry {
         // Sort timings by provider name for consistent output
         timings.sort_by(|a, b| a.name.cmp(&b.name));
 
-        Ok(CollectResult { variables, timings })
+        Ok(CollectResult { variables, numbers, links, timings })
     }
 
     /// Determine which providers are needed based on variables in template
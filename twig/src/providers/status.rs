@@ -0,0 +1,340 @@
+// twig/src/providers/status.rs
+
+//! The last command and the line editor: `{exit_code}`, `{exit_signal}`,
+//! `{exit_pipestatus}`, `{cmd_duration}`, `{jobs}` and `{vi_mode}`
+//!
+//! twig can't see the shell it draws the prompt for, so the shell passes
+//! this state in: `--status`, `--pipestatus`, `--duration-ms`, `--jobs` and
+//! `--keymap`, or the `TWIG_STATUS`, `TWIG_PIPESTATUS`, `TWIG_DURATION_MS`,
+//! `TWIG_JOBS` and `TWIG_KEYMAP` environment variables the `twig init`
+//! scripts set. Values the shell doesn't have are simply left out.
+//!
+//! Every variable is empty when there is nothing to report (success, a
+//! quick command, no jobs, no vi keymap), so conditional spacing drops it:
+//!
+//! ```toml
+//! [prompt]
+//! format = "{vi_mode:yellow}~{cwd}~{cmd_duration:yellow}~{jobs:blue}[ ({exit_signal:red})]~{exit_code:red} $ "
+//!
+//! [cmd]
+//! min_duration_ms = 5000
+//! ```
+
+use super::{Provider, ProviderOutput, ProviderResult};
+use crate::config::Config;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Shortest `{cmd_duration}` shown without `min_duration_ms`
+const DEFAULT_MIN_DURATION_MS: u64 = 2000;
+
+/// What the shell reported about the last command
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LastCommand {
+    pub status: Option<i32>,
+    pub pipestatus: Vec<i32>,
+    pub duration_ms: Option<u64>,
+    pub jobs: Option<u32>,
+    pub keymap: Option<String>,
+}
+
+impl LastCommand {
+    /// Read the values as the shell passes them
+    ///
+    /// Empty or malformed values count as unknown rather than failing the
+    /// prompt: `TWIG_DURATION_MS` is empty after an empty command line.
+    /// The pipeline's statuses are separated by spaces or `|`.
+    pub fn parse(
+        status: Option<&str>,
+        pipestatus: Option<&str>,
+        duration_ms: Option<&str>,
+        jobs: Option<&str>,
+        keymap: Option<&str>,
+    ) -> Self {
+        Self {
+            status: status.and_then(|s| s.trim().parse().ok()),
+            pipestatus: pipestatus
+                .map(|s| s.split([' ', '|']).filter_map(|code| code.parse().ok()).collect())
+                .unwrap_or_default(),
+            duration_ms: duration_ms.and_then(|s| s.trim().parse().ok()),
+            jobs: jobs.and_then(|s| s.trim().parse().ok()),
+            keymap: keymap.map(str::trim).filter(|k| !k.is_empty()).map(str::to_string),
+        }
+    }
+}
+
+/// State passed on the command line, set once from `main`
+static LAST_COMMAND: OnceLock<LastCommand> = OnceLock::new();
+
+/// Set the last command's state for the rest of the run
+pub fn set_last_command(last: LastCommand) {
+    let _ = LAST_COMMAND.set(last);
+}
+
+pub struct StatusProvider;
+
+impl StatusProvider {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Provider for StatusProvider {
+    fn name(&self) -> &str {
+        "status"
+    }
+
+    fn sections(&self) -> Vec<&str> {
+        vec!["exit", "cmd", "jobs", "vi"]
+    }
+
+    fn collect(&self, config: &Config, _validate: bool) -> ProviderResult<ProviderOutput> {
+        let last = LAST_COMMAND.get().cloned().unwrap_or_default();
+        Ok(variables(&last, config))
+    }
+
+    fn default_config(&self) -> HashMap<String, Value> {
+        let mut defaults = HashMap::new();
+        defaults.insert("exit".to_string(), json!({}));
+        defaults.insert("cmd".to_string(), json!({ "min_duration_ms": DEFAULT_MIN_DURATION_MS }));
+        defaults.insert("jobs".to_string(), json!({}));
+        defaults.insert("vi".to_string(), json!({}));
+        defaults
+    }
+}
+
+/// Variables for the configured sections
+fn variables(last: &LastCommand, config: &Config) -> ProviderOutput {
+    let mut output = ProviderOutput::default();
+    let vars = &mut output.variables;
+
+    // Handle [exit] section
+    if config.exit.is_some() {
+        if let Some(code) = last.status {
+            output.numbers.insert("exit_code".to_string(), code as f64);
+            if code != 0 {
+                vars.insert("exit_code".to_string(), code.to_string());
+            }
+            if let Some(name) = signal_name(code) {
+                vars.insert("exit_signal".to_string(), name.to_string());
+            }
+        }
+        // Only worth showing when a command other than the last one failed
+        // (the last one's status is already {exit_code})
+        if let Some((_, earlier)) = last.pipestatus.split_last() {
+            if earlier.iter().any(|&code| code != 0) {
+                let codes: Vec<String> = last.pipestatus.iter().map(i32::to_string).collect();
+                vars.insert("exit_pipestatus".to_string(), codes.join("|"));
+            }
+        }
+    }
+
+    // Handle [cmd] section
+    if let (Some(cmd_config), Some(ms)) = (&config.cmd, last.duration_ms) {
+        output.numbers.insert("cmd_duration".to_string(), ms as f64);
+        if ms >= cmd_config.min_duration_ms.unwrap_or(DEFAULT_MIN_DURATION_MS) {
+            vars.insert("cmd_duration".to_string(), humanize_duration(ms));
+        }
+    }
+
+    // Handle [jobs] section
+    if let (Some(_), Some(jobs)) = (&config.jobs, last.jobs) {
+        output.numbers.insert("jobs".to_string(), jobs as f64);
+        if jobs > 0 {
+            vars.insert("jobs".to_string(), jobs.to_string());
+        }
+    }
+
+    // Handle [vi] section
+    if let (Some(vi_config), Some(mode)) = (&config.vi, last.keymap.as_deref().and_then(vi_mode)) {
+        let symbol = match mode {
+            ViMode::Normal => vi_config.normal.as_deref().unwrap_or("[N]"),
+            ViMode::Insert => vi_config.insert.as_deref().unwrap_or("[I]"),
+            ViMode::Visual => vi_config.visual.as_deref().unwrap_or("[V]"),
+            ViMode::Replace => vi_config.replace.as_deref().unwrap_or("[R]"),
+        };
+        if !symbol.is_empty() {
+            vars.insert("vi_mode".to_string(), symbol.to_string());
+        }
+    }
+
+    output
+}
+
+/// Name of the signal that killed a command with exit status 128+N
+///
+/// Only signals numbered the same on Linux, macOS and the BSDs are named.
+fn signal_name(status: i32) -> Option<&'static str> {
+    let name = match status - 128 {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Short human-readable duration: `850ms`, `4.2s`, `42s`, `3m 7s`, `1h 5m`
+fn humanize_duration(ms: u64) -> String {
+    let seconds = ms / 1000;
+    match ms {
+        0..=999 => format!("{}ms", ms),
+        1000..=9999 => format!("{}.{}s", seconds, ms % 1000 / 100),
+        10_000..=59_999 => format!("{}s", seconds),
+        60_000..=3_599_999 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ViMode {
+    Normal,
+    Insert,
+    Visual,
+    Replace,
+}
+
+/// Vi mode for a keymap name as the shell reports it
+///
+/// zsh: `$KEYMAP` (`vicmd`, `viins`, `main`, `visual`); fish:
+/// `$fish_bind_mode` (`default`, `insert`, `visual`, `replace_one`);
+/// PSReadLine: `Command` / `Insert`. Anything else (emacs keymaps,
+/// `isearch`, ...) has no indicator.
+fn vi_mode(keymap: &str) -> Option<ViMode> {
+    match keymap.to_ascii_lowercase().as_str() {
+        "vicmd" | "default" | "normal" | "command" => Some(ViMode::Normal),
+        "viins" | "main" | "insert" => Some(ViMode::Insert),
+        "visual" => Some(ViMode::Visual),
+        "replace" | "replace_one" => Some(ViMode::Replace),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(sections: &str) -> Config {
+        toml::from_str(&format!("{}\n[prompt]\nformat = \"\"", sections)).unwrap()
+    }
+
+    fn last(status: &str, pipestatus: &str, duration_ms: &str, jobs: &str, keymap: &str) -> LastCommand {
+        LastCommand::parse(Some(status), Some(pipestatus), Some(duration_ms), Some(jobs), Some(keymap))
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed = last("130", "0 1 130", "", "2", "vicmd");
+        assert_eq!(parsed.status, Some(130));
+        assert_eq!(parsed.pipestatus, vec![0, 1, 130]);
+        assert_eq!(parsed.duration_ms, None);
+        assert_eq!(parsed.jobs, Some(2));
+        assert_eq!(parsed.keymap.as_deref(), Some("vicmd"));
+
+        assert_eq!(LastCommand::parse(Some("x"), Some("0|1"), None, None, Some("")).pipestatus, vec![0, 1]);
+        assert_eq!(LastCommand::parse(Some("x"), None, None, None, Some("")), LastCommand::default());
+    }
+
+    #[test]
+    fn test_exit_variables() {
+        let config = config("[exit]");
+        let output = variables(&last("0", "0", "", "", ""), &config);
+        assert!(output.variables.is_empty());
+        assert_eq!(output.numbers["exit_code"], 0.0);
+
+        let output = variables(&last("130", "0 130", "", "", ""), &config);
+        assert_eq!(output.variables["exit_code"], "130");
+        assert_eq!(output.variables["exit_signal"], "SIGINT");
+        // Only the last command failed: {exit_code} already says so
+        assert!(!output.variables.contains_key("exit_pipestatus"));
+        let output = variables(&last("1", "0 1", "", "", ""), &config);
+        assert_eq!(output.variables["exit_code"], "1");
+        assert!(!output.variables.contains_key("exit_pipestatus"));
+        let output = variables(&last("130", "1 130", "", "", ""), &config);
+        assert_eq!(output.variables["exit_pipestatus"], "1|130");
+
+        // A failure earlier in the pipeline
+        let output = variables(&last("0", "1 0", "", "", ""), &config);
+        assert!(!output.variables.contains_key("exit_code"));
+        assert_eq!(output.variables["exit_pipestatus"], "1|0");
+    }
+
+    #[test]
+    fn test_signal_name() {
+        assert_eq!(signal_name(137), Some("SIGKILL"));
+        assert_eq!(signal_name(139), Some("SIGSEGV"));
+        assert_eq!(signal_name(1), None);
+        assert_eq!(signal_name(128), None);
+        assert_eq!(signal_name(255), None);
+    }
+
+    #[test]
+    fn test_cmd_duration() {
+        let output = variables(&last("0", "", "1500", "", ""), &config("[cmd]"));
+        assert!(output.variables.is_empty());
+        assert_eq!(output.numbers["cmd_duration"], 1500.0);
+
+        let output = variables(&last("0", "", "1500", "", ""), &config("[cmd]\nmin_duration_ms = 1000"));
+        assert_eq!(output.variables["cmd_duration"], "1.5s");
+
+        // Not reported: no variable, no number
+        assert!(variables(&LastCommand::default(), &config("[cmd]")).numbers.is_empty());
+    }
+
+    #[test]
+    fn test_humanize_duration() {
+        assert_eq!(humanize_duration(850), "850ms");
+        assert_eq!(humanize_duration(4250), "4.2s");
+        assert_eq!(humanize_duration(42_000), "42s");
+        assert_eq!(humanize_duration(187_000), "3m 7s");
+        assert_eq!(humanize_duration(3_900_000), "1h 5m");
+    }
+
+    #[test]
+    fn test_jobs() {
+        let config = config("[jobs]");
+        assert!(variables(&last("0", "", "", "0", ""), &config).variables.is_empty());
+        assert_eq!(variables(&last("0", "", "", "3", ""), &config).variables["jobs"], "3");
+    }
+
+    #[test]
+    fn test_vi_mode() {
+        let config = config("[vi]\ninsert = \"\"\nnormal = \"❮\"");
+        let mode = |keymap: &str| variables(&last("0", "", "", "", keymap), &config).variables.remove("vi_mode");
+        assert_eq!(mode("vicmd").as_deref(), Some("❮"));
+        assert_eq!(mode("default").as_deref(), Some("❮"));
+        assert_eq!(mode("visual").as_deref(), Some("[V]"));
+        // Hidden in insert mode, nothing for non-vi keymaps
+        assert_eq!(mode("viins"), None);
+        assert_eq!(mode("emacs"), None);
+    }
+
+    #[test]
+    fn test_rules_use_raw_numbers() {
+        let config = config(
+            "[[cmd.rules]]\nwhen = \"duration >= 60000\"\nstyle = \"red\"\n[[exit.rules]]\nwhen = \"code == 130\"\nhide = true",
+        );
+        let mut output = variables(&last("130", "", "65000", "", ""), &config);
+        let styles = crate::rules::apply_rules(&config, &mut output.variables, &output.numbers);
+        assert_eq!(styles["cmd_duration"], ["red"]);
+        assert!(!output.variables.contains_key("exit_code"));
+        assert_eq!(output.variables["exit_signal"], "SIGINT");
+    }
+
+    #[test]
+    fn test_unconfigured_sections() {
+        let output = variables(&last("1", "1 1", "5000", "1", "vicmd"), &config(""));
+        assert!(output.variables.is_empty());
+        assert!(output.numbers.is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+// twig/src/rules.rs
+
+//! Threshold rules on provider sections
+//!
+//! ```toml
+//! [[battery.rules]]
+//! when = "percentage < 20"
+//! style = "red,bold"
+//!
+//! [[battery.rules]]
+//! when = "percentage < 50"
+//! style = "yellow"
+//!
+//! [[battery.rules]]
+//! when = "percentage >= 95"
+//! hide = true
+//! ```
+//!
+//! Rules are evaluated once per prompt, after providers have run, against the
+//! raw numbers providers report next to their formatted variables. A matching
+//! rule either hides its variable (removing it, so groups and conditional
+//! spaces collapse) or adds a style on top of the variable's own style.
+
+use crate::config::{Config, SECTIONS};
+use crate::style;
+use std::collections::{HashMap, HashSet};
+
+/// Comparison operator in a rule condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+/// Operators in matching order (two-character operators first)
+const OPERATORS: [(&str, Op); 6] = [
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+];
+
+/// A parsed `when` condition: `<name> <op> <number>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    /// Number name as written, relative to the section
+    pub name: String,
+    op: Op,
+    value: f64,
+}
+
+impl Condition {
+    /// Parse a condition such as `percentage < 20`
+    pub fn parse(when: &str) -> Result<Condition, String> {
+        let (index, symbol, op) = OPERATORS
+            .iter()
+            .filter_map(|(symbol, op)| when.find(symbol).map(|i| (i, *symbol, *op)))
+            // Leftmost operator; at equal positions the two-character one (listed first)
+            .min_by_key(|(i, _, _)| *i)
+            .ok_or_else(|| format!("Missing comparison operator in '{}' (use <, <=, >, >=, == or !=)", when))?;
+
+        let name = when[..index].trim();
+        let value = when[index + symbol.len()..].trim();
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid name '{}' in '{}'", name, when));
+        }
+        let value = value
+            .parse::<f64>()
+            .map_err(|_| format!("Expected a number after '{}' in '{}', got '{}'", symbol, when, value))?;
+
+        Ok(Condition {
+            name: name.to_string(),
+            op,
+            value,
+        })
+    }
+
+    pub fn matches(&self, number: f64) -> bool {
+        match self.op {
+            Op::Lt => number < self.value,
+            Op::Le => number <= self.value,
+            Op::Gt => number > self.value,
+            Op::Ge => number >= self.value,
+            Op::Eq => number == self.value,
+            Op::Ne => number != self.value,
+        }
+    }
+}
+
+/// Full variable name for a name used in a section's rules
+///
+/// `percentage` in `[battery]` -> `battery_percentage`; names that already
+/// carry the section prefix are kept as-is.
+pub fn qualify(section: &str, name: &str) -> String {
+    let prefixed = name
+        .strip_prefix(section)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'));
+    if prefixed {
+        name.to_string()
+    } else {
+        format!("{}_{}", section, name)
+    }
+}
+
+/// Evaluate all section rules
+///
+/// Hidden variables are removed from `variables`; the returned map holds the
+/// extra style parts for variables whose matching rule has a style.
+/// Rules with invalid conditions are skipped (`twig --validate` reports them).
+pub fn apply_rules(
+    config: &Config,
+    variables: &mut HashMap<String, String>,
+    numbers: &HashMap<String, f64>,
+) -> HashMap<String, Vec<String>> {
+    let mut styles = HashMap::new();
+    // Variables whose first matching rule has been found
+    let mut decided = HashSet::new();
+
+    for section in SECTIONS {
+        for rule in config.section_rules(section).unwrap_or_default() {
+            let Ok(condition) = Condition::parse(&rule.when) else {
+                continue;
+            };
+            let name = qualify(section, &condition.name);
+            let target = match &rule.target {
+                Some(target) => qualify(section, target),
+                None => name.clone(),
+            };
+            if decided.contains(&target) {
+                continue;
+            }
+
+            let Some(&number) = numbers.get(&name) else {
+                continue;
+            };
+            if !condition.matches(number) {
+                continue;
+            }
+
+            if rule.hide {
+                variables.remove(&target);
+            } else if let Some(spec) = &rule.style {
+                styles.insert(target.clone(), style::split_spec(spec));
+            }
+            decided.insert(target);
+        }
+    }
+
+    styles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn battery_config(rules: &str) -> Config {
+        toml::from_str(&format!("{}\n[prompt]\nformat = \"{{battery_percentage}}\"", rules)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_condition() {
+        let condition = Condition::parse("percentage < 20").unwrap();
+        assert_eq!(condition.name, "percentage");
+        assert!(condition.matches(19.0));
+        assert!(!condition.matches(20.0));
+
+        assert!(Condition::parse("staged>=10").unwrap().matches(10.0));
+        assert!(Condition::parse("power != 0").unwrap().matches(-11.7));
+        assert!(Condition::parse("power == -1.5").unwrap().matches(-1.5));
+    }
+
+    #[test]
+    fn test_parse_condition_errors() {
+        assert!(Condition::parse("percentage").unwrap_err().contains("operator"));
+        assert!(Condition::parse("percentage < low").unwrap_err().contains("number"));
+        assert!(Condition::parse("< 20").unwrap_err().contains("name"));
+        assert!(Condition::parse("a b < 20").is_err());
+    }
+
+    #[test]
+    fn test_qualify() {
+        assert_eq!(qualify("battery", "percentage"), "battery_percentage");
+        assert_eq!(qualify("battery", "battery_percentage"), "battery_percentage");
+        assert_eq!(qualify("git", "status_staged"), "git_status_staged");
+        assert_eq!(qualify("git", "gitlab"), "git_gitlab");
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let config = battery_config(
+            r#"
+            [[battery.rules]]
+            when = "percentage < 20"
+            style = "red,bold"
+
+            [[battery.rules]]
+            when = "percentage < 50"
+            style = "yellow"
+            "#,
+        );
+        let apply = |percentage: f64| {
+            let mut vars = HashMap::from([("battery_percentage".to_string(), format!("{}%", percentage))]);
+            let numbers = HashMap::from([("battery_percentage".to_string(), percentage)]);
+            apply_rules(&config, &mut vars, &numbers).remove("battery_percentage")
+        };
+
+        assert_eq!(apply(15.0), Some(vec!["red".to_string(), "bold".to_string()]));
+        assert_eq!(apply(35.0), Some(vec!["yellow".to_string()]));
+        assert_eq!(apply(85.0), None);
+    }
+
+    #[test]
+    fn test_hide_and_target() {
+        let config = battery_config(
+            r#"
+            [[battery.rules]]
+            when = "percentage >= 95"
+            hide = true
+
+            [[battery.rules]]
+            when = "power < 0"
+            target = "status"
+            style = "red"
+            "#,
+        );
+        let mut vars = HashMap::from([
+            ("battery_percentage".to_string(), "98%".to_string()),
+            ("battery_status".to_string(), "Discharging".to_string()),
+        ]);
+        let numbers = HashMap::from([
+            ("battery_percentage".to_string(), 98.0),
+            ("battery_power".to_string(), -11.7),
+        ]);
+
+        let styles = apply_rules(&config, &mut vars, &numbers);
+        assert!(!vars.contains_key("battery_percentage"));
+        assert_eq!(styles.get("battery_status"), Some(&vec!["red".to_string()]));
+    }
+
+    #[test]
+    fn test_missing_numbers_never_match() {
+        let config = battery_config(
+            r#"
+            [[battery.rules]]
+            when = "percentage < 20"
+            hide = true
+            "#,
+        );
+        let mut vars = HashMap::new();
+        let styles = apply_rules(&config, &mut vars, &HashMap::new());
+        assert!(styles.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use super::ShellFormatter;
+use super::{replace_tokens, ShellFormatter};
 
 /// Bash formatter with \[...\] wrapping
 ///
@@ -11,6 +11,37 @@ impl ShellFormatter for BashFormatter {
         // Wrap ANSI codes in \[...\]
         format!("\\[{}\\]{}\\[{}\\]", ansi_code, text, reset_code)
     }
+
+    fn escape_text(&self, text: &str) -> String {
+        // PS1 is decoded (\u, \w, ...) and then, under the default `promptvars`,
+        // expanded like a double-quoted string: a literal \, $ or ` has to
+        // survive both steps. "\\\\" decodes to "\\", which expands to "\".
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\\\\\"),
+                '$' | '`' => {
+                    escaped.push_str("\\\\");
+                    escaped.push(c);
+                }
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    fn visible_text(&self, output: &str) -> String {
+        replace_tokens(output, r"\\\[|\\\]|\\{4}|\\{2}[$`]", |token| match token {
+            "\\[" | "\\]" => String::new(),
+            // `\\\\` shows one backslash and `\\$` a dollar sign
+            _ => token[token.len() - 1..].to_string(),
+        })
+    }
+
+    fn position_right(&self, right: &str, column: usize) -> String {
+        // The whole right prompt is non-printing as far as readline is concerned
+        format!("\\[\x1b[s\x1b[{}G{}\x1b[u\\]", column, right)
+    }
 }
 
 #[cfg(test)]
@@ -23,4 +54,31 @@ mod tests {
         let result = formatter.format_ansi("\x1b[36m", "test", "\x1b[0m");
         assert_eq!(result, "\\[\x1b[36m\\]test\\[\x1b[0m\\]");
     }
+
+    #[test]
+    fn test_bash_link() {
+        let formatter = BashFormatter;
+        let result = formatter.format_link("file://h/tmp", "tmp");
+        assert_eq!(result, "\\[\x1b]8;;file://h/tmp\x07\\]tmp\\[\x1b]8;;\x07\\]");
+    }
+
+    #[test]
+    fn test_bash_escape_text() {
+        let formatter = BashFormatter;
+        assert_eq!(formatter.escape_text("$(id)"), "\\\\$(id)");
+        assert_eq!(formatter.escape_text("`id`"), "\\\\`id\\\\`");
+        assert_eq!(formatter.escape_text("${HOME}"), "\\\\${HOME}");
+        assert_eq!(formatter.escape_text("C:\\new"), "C:\\\\\\\\new");
+        // Prompt escapes and wrappers in a value stay literal
+        assert_eq!(formatter.escape_text("\\u@\\h \\[x\\]"), "\\\\\\\\u@\\\\\\\\h \\\\\\\\[x\\\\\\\\]");
+        // Nothing else is special
+        assert_eq!(formatter.escape_text("100% ! 'q' \"dq\""), "100% ! 'q' \"dq\"");
+    }
+
+    #[test]
+    fn test_bash_position_right() {
+        let formatter = BashFormatter;
+        let result = formatter.position_right("\x1b[2m12:00\x1b[0m", 76);
+        assert_eq!(result, "\\[\x1b[s\x1b[76G\x1b[2m12:00\x1b[0m\x1b[u\\]");
+    }
 }
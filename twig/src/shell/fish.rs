@@ -0,0 +1,40 @@
+use super::ShellFormatter;
+
+/// Fish formatter - no wrapping
+///
+/// Fish prints whatever `fish_prompt` writes and works out the width of the
+/// escape sequences itself, so codes are emitted as-is. The prompt is written
+/// straight to stdout rather than through a command substitution, which keeps
+/// its newlines intact.
+pub struct FishFormatter;
+
+impl ShellFormatter for FishFormatter {
+    fn format_ansi(&self, ansi_code: &str, text: &str, reset_code: &str) -> String {
+        // No wrapping, just concatenate: ANSI code + text + reset
+        format!("{}{}{}", ansi_code, text, reset_code)
+    }
+
+    fn native_right_prompt(&self) -> bool {
+        // Fish draws fish_right_prompt itself
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fish_formatter() {
+        let formatter = FishFormatter;
+        let result = formatter.format_ansi("\x1b[36m", "test", "\x1b[0m");
+        assert_eq!(result, "\x1b[36mtest\x1b[0m");
+    }
+
+    #[test]
+    fn test_fish_keeps_newlines() {
+        let formatter = FishFormatter;
+        assert_eq!(formatter.finalize("line1\nline2 % ! $ "), "line1\nline2 % ! $ ");
+        assert!(formatter.native_right_prompt());
+    }
+}
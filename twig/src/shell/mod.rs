@@ -1,13 +1,22 @@
 mod bash;
+mod fish;
+mod nu;
+mod pwsh;
 mod raw;
 mod tcsh;
 mod zsh;
 
 pub use bash::BashFormatter;
-pub use raw::RawFormatter;
+pub use fish::FishFormatter;
+pub use nu::NuFormatter;
+pub use pwsh::PwshFormatter;
+pub use raw::{RawFormatter, UnwrappedFormatter};
 pub use tcsh::TcshFormatter;
 pub use zsh::ZshFormatter;
 
+use crate::hyperlink;
+use regex::{Captures, Regex};
+
 /// Trait for shell-specific ANSI escape code formatting
 pub trait ShellFormatter {
     /// Format ANSI escape codes with shell-specific wrapping
@@ -21,6 +30,37 @@ pub trait ShellFormatter {
     /// Formatted string with shell-specific wrapping around ANSI codes
     fn format_ansi(&self, ansi_code: &str, text: &str, reset_code: &str) -> String;
 
+    /// Escape text so the shell shows it literally
+    ///
+    /// Applied to placeholder values and literals (after filters, before
+    /// codes are wrapped around them), so a directory or branch name can't
+    /// inject prompt sequences such as `%n`, `\u` or `$(...)`. The
+    /// template's own text goes through
+    /// [`escape_template_text`](Self::escape_template_text) instead.
+    /// Escape codes are never passed through here.
+    fn escape_text(&self, text: &str) -> String {
+        // Default implementation: the shell shows the output as-is
+        text.to_string()
+    }
+
+    /// Escape the template's own text
+    ///
+    /// Native escapes written in the format (zsh `%n`, bash `\u`) are the
+    /// user's to use, so by default the text passes through unchanged.
+    fn escape_template_text(&self, text: &str) -> String {
+        text.to_string()
+    }
+
+    /// What the shell shows for `output`: its zero-width wrappers removed and
+    /// [`escape_text`](Self::escape_text) undone, for measuring the prompt
+    ///
+    /// ANSI codes are stripped separately; only this shell's own wrappers and
+    /// escapes are recognized, so text meant literally is measured as such.
+    fn visible_text(&self, output: &str) -> String {
+        // Default implementation: the shell shows the output as-is
+        output.to_string()
+    }
+
     /// Post-process the final output string for shell-specific requirements
     ///
     /// For example, TCSH and Zsh need literal `\n` instead of actual newlines.
@@ -34,6 +74,36 @@ pub trait ShellFormatter {
         // Default implementation: no post-processing
         output.to_string()
     }
+
+    /// Make `text` an OSC 8 hyperlink to `url`
+    ///
+    /// The link sequences are zero-width and wrapped like color codes.
+    /// `url` is percent-encoded (see [`crate::hyperlink`]).
+    fn format_link(&self, url: &str, text: &str) -> String {
+        self.format_ansi(&hyperlink::open(url), text, hyperlink::CLOSE)
+    }
+
+    /// Whether the shell draws a right prompt itself (zsh `RPROMPT`, tcsh `rprompt`,
+    /// fish `fish_right_prompt`, nu `PROMPT_COMMAND_RIGHT`)
+    ///
+    /// For other shells the right prompt is positioned with cursor movement
+    /// (see [`ShellFormatter::position_right`]).
+    fn native_right_prompt(&self) -> bool {
+        false
+    }
+
+    /// Draw a right prompt at `column` (1-based) and return the cursor
+    ///
+    /// # Arguments
+    /// * `right` - The rendered right prompt, with raw (unwrapped) ANSI codes
+    /// * `column` - Column the right prompt starts at
+    ///
+    /// # Returns
+    /// A zero-width sequence to insert at the start of the prompt's last line
+    fn position_right(&self, right: &str, column: usize) -> String {
+        // Save cursor, move to column, draw, restore cursor
+        format!("\x1b[s\x1b[{}G{}\x1b[u", column, right)
+    }
 }
 
 /// Shell output modes
@@ -47,6 +117,12 @@ pub enum ShellMode {
     Zsh,
     /// TCSH format with %{...%} wrapping
     Tcsh,
+    /// Fish format: raw ANSI codes, printed by fish_prompt
+    Fish,
+    /// PowerShell format: raw ANSI codes, returned by the prompt function
+    Pwsh,
+    /// Nushell format: raw ANSI codes, returned by $env.PROMPT_COMMAND
+    Nu,
 }
 
 /// Factory function to create shell formatter based on mode
@@ -56,5 +132,17 @@ pub fn get_formatter(mode: ShellMode) -> Box<dyn ShellFormatter> {
         ShellMode::Bash => Box::new(BashFormatter),
         ShellMode::Zsh => Box::new(ZshFormatter),
         ShellMode::Tcsh => Box::new(TcshFormatter),
+        ShellMode::Fish => Box::new(FishFormatter),
+        ShellMode::Pwsh => Box::new(PwshFormatter),
+        ShellMode::Nu => Box::new(NuFormatter),
     }
 }
+
+/// Replace every match of `tokens` (scanned left to right) by what it shows
+///
+/// Shared by the `visible_text` implementations: `shown` maps an escape or
+/// wrapper to its visible text.
+fn replace_tokens(output: &str, tokens: &str, shown: impl Fn(&str) -> String) -> String {
+    let re = Regex::new(tokens).unwrap();
+    re.replace_all(output, |caps: &Captures| shown(&caps[0])).into_owned()
+}
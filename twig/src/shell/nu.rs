@@ -0,0 +1,40 @@
+use super::ShellFormatter;
+
+/// Nushell formatter - no wrapping
+///
+/// `$env.PROMPT_COMMAND` is a closure whose result is shown as-is: twig's
+/// output is never parsed, so `$`, backticks and newlines in values need no
+/// escaping, and reedline works out the width of the escape codes itself.
+pub struct NuFormatter;
+
+impl ShellFormatter for NuFormatter {
+    fn format_ansi(&self, ansi_code: &str, text: &str, reset_code: &str) -> String {
+        // No wrapping, just concatenate: ANSI code + text + reset
+        format!("{}{}{}", ansi_code, text, reset_code)
+    }
+
+    fn native_right_prompt(&self) -> bool {
+        // Nushell draws $env.PROMPT_COMMAND_RIGHT itself
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nu_formatter() {
+        let formatter = NuFormatter;
+        let result = formatter.format_ansi("\x1b[36m", "test", "\x1b[0m");
+        assert_eq!(result, "\x1b[36mtest\x1b[0m");
+        assert!(formatter.native_right_prompt());
+    }
+
+    #[test]
+    fn test_nu_output_is_literal() {
+        let formatter = NuFormatter;
+        let value = "$env.HOME `ls` (date now) {x}\nnext";
+        assert_eq!(formatter.finalize(value), value);
+    }
+}
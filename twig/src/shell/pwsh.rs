@@ -0,0 +1,41 @@
+use super::ShellFormatter;
+
+/// PowerShell formatter - no wrapping
+///
+/// The `prompt` function returns twig's output as a plain string, so nothing
+/// in it is evaluated: `$`, backticks and quotes in values need no escaping,
+/// and PSReadLine works out the width of the escape codes itself. PowerShell
+/// splits a native command's output into lines, so the `prompt` function
+/// joins them back with `` "`n" `` (see `shell/init.ps1`, printed by `twig init pwsh`).
+///
+/// PowerShell has no right prompt of its own; it is positioned with cursor
+/// movement like bash's.
+pub struct PwshFormatter;
+
+impl ShellFormatter for PwshFormatter {
+    fn format_ansi(&self, ansi_code: &str, text: &str, reset_code: &str) -> String {
+        // No wrapping, just concatenate: ANSI code + text + reset
+        format!("{}{}{}", ansi_code, text, reset_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pwsh_formatter() {
+        let formatter = PwshFormatter;
+        let result = formatter.format_ansi("\x1b[36m", "test", "\x1b[0m");
+        assert_eq!(result, "\x1b[36mtest\x1b[0m");
+        assert!(!formatter.native_right_prompt());
+    }
+
+    #[test]
+    fn test_pwsh_output_is_literal() {
+        // The output is data, never parsed: values pass through unchanged
+        let formatter = PwshFormatter;
+        let value = "$env:HOME `whoami` 'q' \"dq\"\nnext";
+        assert_eq!(formatter.finalize(value), value);
+    }
+}
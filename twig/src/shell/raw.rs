@@ -13,9 +13,35 @@ impl ShellFormatter for RawFormatter {
     }
 }
 
+/// Another shell's escaping without its wrapping
+///
+/// For text that ends up inside a larger zero-width sequence, such as the
+/// right prompt bash draws with cursor movement: values still have to be
+/// escaped for the shell, but codes must not be wrapped a second time.
+pub struct UnwrappedFormatter<'a>(pub &'a dyn ShellFormatter);
+
+impl ShellFormatter for UnwrappedFormatter<'_> {
+    fn format_ansi(&self, ansi_code: &str, text: &str, reset_code: &str) -> String {
+        RawFormatter.format_ansi(ansi_code, text, reset_code)
+    }
+
+    fn escape_text(&self, text: &str) -> String {
+        self.0.escape_text(text)
+    }
+
+    fn escape_template_text(&self, text: &str) -> String {
+        self.0.escape_template_text(text)
+    }
+
+    fn visible_text(&self, output: &str) -> String {
+        self.0.visible_text(output)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::shell::BashFormatter;
 
     #[test]
     fn test_raw_formatter() {
@@ -23,4 +49,12 @@ mod tests {
         let result = formatter.format_ansi("\x1b[36m", "test", "\x1b[0m");
         assert_eq!(result, "\x1b[36mtest\x1b[0m");
     }
+
+    #[test]
+    fn test_unwrapped_formatter() {
+        let formatter = UnwrappedFormatter(&BashFormatter);
+        assert_eq!(formatter.format_ansi("\x1b[36m", "x", "\x1b[0m"), "\x1b[36mx\x1b[0m");
+        assert_eq!(formatter.escape_text("$(id)"), "\\\\$(id)");
+        assert_eq!(formatter.visible_text("\\\\$(id)"), "$(id)");
+    }
 }
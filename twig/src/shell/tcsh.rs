@@ -1,4 +1,5 @@
-use super::ShellFormatter;
+use super::{replace_tokens, ShellFormatter};
+use crate::hyperlink;
 
 /// TCSH formatter with %{...%} wrapping
 ///
@@ -15,26 +16,68 @@ impl ShellFormatter for TcshFormatter {
         format!("%{{{}%}}{}%{{{}%}}", ansi_code, text, reset_code)
     }
 
+    fn escape_text(&self, text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                // "%" starts a prompt escape (e.g., %n for username, %/ for path)
+                '%' => escaped.push_str("%%"),
+                // "!" is the history number; \c and ^c are parsed as in bindkey
+                '!' | '\\' | '^' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    fn escape_template_text(&self, text: &str) -> String {
+        // As twig has always done for tcsh: "!" and "%" in the format are
+        // literal, except a written %{ or %} wrapper
+        let mut escaped = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '!' => escaped.push_str("\\!"),
+                '%' if !matches!(chars.peek(), Some('{' | '}')) => escaped.push_str("%%"),
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    fn visible_text(&self, output: &str) -> String {
+        replace_tokens(output, r"%%|%\{.*?%\}|\\.", |token| match token {
+            "%%" => "%".to_string(),
+            "\\n" => "\n".to_string(),
+            _ if token.starts_with("%{") => String::new(),
+            _ => token[1..].to_string(),
+        })
+    }
+
     fn finalize(&self, output: &str) -> String {
         // TCSH needs literal \n instead of actual newline characters
+        // (text was escaped by escape_text, so any other \ is already doubled)
         let output = output.replace('\n', "\\n");
 
-        // Escape ! for TCSH history expansion
-        // In tcsh, "!" triggers history expansion, so we escape it to "\!"
-        let output = output.replace('!', "\\!");
-
-        // Escape % for TCSH prompt formatting
-        // In tcsh, "%" is special (e.g., %n for username, %/ for path)
-        // We need to escape literal "%" to "%%" but preserve our formatting %{ and %}
-        let output = output.replace('%', "%%");
-        let output = output.replace("%%{", "%{");
-        let output = output.replace("%%}", "%}");
-
         // Fix edge case: when %} is immediately followed by \n, tcsh doesn't parse
         // the newline correctly. Insert a space between them.
         // The space is invisible at the end of the line but allows tcsh to parse the \n.
         output.replace("%}\\n", "%} \\n")
     }
+
+    fn format_link(&self, url: &str, text: &str) -> String {
+        // Prompt escapes are expanded inside %{...%} too: keep the URL's %XX literal
+        let url = url.replace('%', "%%");
+        self.format_ansi(&hyperlink::open(&url), text, hyperlink::CLOSE)
+    }
+
+    fn native_right_prompt(&self) -> bool {
+        // TCSH draws rprompt itself
+        true
+    }
 }
 
 #[cfg(test)]
@@ -48,6 +91,13 @@ mod tests {
         assert_eq!(result, "%{\x1b[36m%}test%{\x1b[0m%}");
     }
 
+    #[test]
+    fn test_tcsh_link() {
+        let formatter = TcshFormatter;
+        let result = formatter.finalize(&formatter.format_link("file://h/a%20b", "a b"));
+        assert_eq!(result, "%{\x1b]8;;file://h/a%%20b\x07%}a b%{\x1b]8;;\x07%}");
+    }
+
     #[test]
     fn test_tcsh_finalize_newline() {
         let formatter = TcshFormatter;
@@ -66,31 +116,46 @@ mod tests {
     }
 
     #[test]
-    fn test_tcsh_finalize_exclamation_escaping() {
+    fn test_tcsh_exclamation_escaping() {
         let formatter = TcshFormatter;
         // Test that ! is escaped to \! for tcsh history expansion
-        let input = "! ";
-        let expected = "\\! ";
-        assert_eq!(formatter.finalize(input), expected);
+        assert_eq!(formatter.escape_text("! "), "\\! ");
 
         // Test with formatted prompt
-        let input = "%{\x1b[37m\x1b[1m%}!%{\x1b[0m%} ";
-        let expected = "%{\x1b[37m\x1b[1m%}\\!%{\x1b[0m%} ";
-        assert_eq!(formatter.finalize(input), expected);
+        let result = formatter.format_ansi("\x1b[37m\x1b[1m", &formatter.escape_text("!"), "\x1b[0m");
+        assert_eq!(formatter.finalize(&result), "%{\x1b[37m\x1b[1m%}\\!%{\x1b[0m%}");
     }
 
     #[test]
-    fn test_tcsh_finalize_percent_escaping() {
+    fn test_tcsh_percent_escaping() {
         let formatter = TcshFormatter;
         // Test that % is escaped to %% for tcsh prompt formatting
-        // but %{ and %} are preserved for ANSI wrapping
-        let input = "%{\x1b[33m%}85%%{\x1b[0m%}";
-        let expected = "%{\x1b[33m%}85%%%{\x1b[0m%}";
-        assert_eq!(formatter.finalize(input), expected);
+        // while the %{ and %} around codes are left alone
+        let result = formatter.format_ansi("\x1b[33m", &formatter.escape_text("85%"), "\x1b[0m");
+        assert_eq!(formatter.finalize(&result), "%{\x1b[33m%}85%%%{\x1b[0m%}");
 
         // Test multiple percent signs
-        let input = "100% complete";
-        let expected = "100%% complete";
-        assert_eq!(formatter.finalize(input), expected);
+        assert_eq!(formatter.escape_text("100% complete"), "100%% complete");
+    }
+
+    #[test]
+    fn test_tcsh_template_text_escaping() {
+        let formatter = TcshFormatter;
+        // "!" and "%" written in the format stay literal
+        assert_eq!(formatter.finalize(&formatter.escape_template_text("100% done! > ")), "100%% done\\! > ");
+        // A wrapper written by hand is kept
+        assert_eq!(formatter.escape_template_text("%{\x1b[1m%}!"), "%{\x1b[1m%}\\!");
+    }
+
+    #[test]
+    fn test_tcsh_adversarial_text() {
+        let formatter = TcshFormatter;
+        // A directory literally named "%{x" used to come out as an unclosed %{
+        let result = formatter.finalize(&formatter.escape_text("/tmp/%{x"));
+        assert_eq!(result, "/tmp/%%{x");
+        let result = formatter.finalize(&formatter.escape_text("%}!!\\n^G"));
+        assert_eq!(result, "%%}\\!\\!\\\\n\\^G");
+        // $ and backticks aren't expanded in tcsh prompts
+        assert_eq!(formatter.escape_text("$HOME `id`"), "$HOME `id`");
     }
 }
@@ -1,4 +1,5 @@
-use super::ShellFormatter;
+use super::{replace_tokens, ShellFormatter};
+use crate::hyperlink;
 
 /// Zsh formatter with %{...%} wrapping
 ///
@@ -12,6 +13,35 @@ impl ShellFormatter for ZshFormatter {
         format!("%{{{}%}}{}%{{{}%}}", ansi_code, text, reset_code)
     }
 
+    fn escape_text(&self, text: &str) -> String {
+        // % starts a prompt escape. Under PROMPT_SUBST the prompt is expanded
+        // like a double-quoted string first, so \, $ and ` are backslashed;
+        // under PROMPT_BANG "!" is the history number and "!!" a literal "!".
+        // `twig init zsh` turns both options on, as most themes do.
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '%' => escaped.push_str("%%"),
+                '!' => escaped.push_str("!!"),
+                '\\' | '$' | '`' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    fn visible_text(&self, output: &str) -> String {
+        replace_tokens(output, r"%%|%\{.*?%\}|!!|\\[\\$`]", |token| match token {
+            "%%" => "%".to_string(),
+            "!!" => "!".to_string(),
+            _ if token.starts_with('\\') => token[1..].to_string(),
+            _ => String::new(),
+        })
+    }
+
     fn finalize(&self, output: &str) -> String {
         // Zsh needs literal \n instead of actual newline characters
         let output = output.replace('\n', "\\n");
@@ -21,6 +51,17 @@ impl ShellFormatter for ZshFormatter {
         // The space is invisible at the end of the line but allows zsh to parse the \n.
         output.replace("%}\\n", "%} \\n")
     }
+
+    fn format_link(&self, url: &str, text: &str) -> String {
+        // Prompt escapes are expanded inside %{...%} too: keep the URL's %XX literal
+        let url = url.replace('%', "%%");
+        self.format_ansi(&hyperlink::open(&url), text, hyperlink::CLOSE)
+    }
+
+    fn native_right_prompt(&self) -> bool {
+        // Zsh draws RPROMPT itself
+        true
+    }
 }
 
 #[cfg(test)]
@@ -34,6 +75,50 @@ mod tests {
         assert_eq!(result, "%{\x1b[36m%}test%{\x1b[0m%}");
     }
 
+    #[test]
+    fn test_zsh_link() {
+        let formatter = ZshFormatter;
+        let result = formatter.format_link("file://h/a%20b", "a b");
+        assert_eq!(result, "%{\x1b]8;;file://h/a%%20b\x07%}a b%{\x1b]8;;\x07%}");
+    }
+
+    #[test]
+    fn test_zsh_escape_text() {
+        let formatter = ZshFormatter;
+        assert_eq!(formatter.escape_text("100%"), "100%%");
+        // A directory named like a prompt escape or a wrapper
+        assert_eq!(formatter.escape_text("%n%~%{x%}"), "%%n%%~%%{x%%}");
+        assert_eq!(formatter.escape_text("!"), "!!");
+        assert_eq!(formatter.escape_text("C:\\new"), "C:\\\\new");
+    }
+
+    #[test]
+    fn test_zsh_command_substitution_is_escaped() {
+        let formatter = ZshFormatter;
+        assert_eq!(formatter.escape_text("$(rm -rf ~)"), "\\$(rm -rf ~)");
+        assert_eq!(formatter.escape_text("`id`"), "\\`id\\`");
+        assert_eq!(formatter.escape_text("${HOME}"), "\\${HOME}");
+        assert_eq!(formatter.escape_text("$((1+1))"), "\\$((1+1))");
+        // Quotes and the rest need nothing
+        assert_eq!(formatter.escape_text("'q' \"dq\" ~ ^"), "'q' \"dq\" ~ ^");
+    }
+
+    #[test]
+    fn test_zsh_visible_text() {
+        let formatter = ZshFormatter;
+        let value = "100% $(id) `id` \\ !";
+        let output = formatter.format_ansi("\x1b[32m", &formatter.escape_text(value), "\x1b[0m");
+        assert_eq!(formatter.visible_text(&output), value);
+    }
+
+    #[test]
+    fn test_zsh_escaped_value_keeps_wrapping() {
+        let formatter = ZshFormatter;
+        let text = formatter.escape_text("%{evil%}");
+        let result = formatter.finalize(&formatter.format_ansi("\x1b[32m", &text, "\x1b[0m"));
+        assert_eq!(result, "%{\x1b[32m%}%%{evil%%}%{\x1b[0m%}");
+    }
+
     #[test]
     fn test_zsh_finalize_newline() {
         let formatter = ZshFormatter;
@@ -0,0 +1,656 @@
+// twig/src/style.rs
+
+//! Style specs: colors, attributes and color depth handling
+//!
+//! A style spec is a list of parts such as `cyan,bold` or `bg:#2e3440,white`.
+//! Colors can be given as:
+//! - the 16 named ANSI colors (`red`, `bright_black`, ...)
+//! - `#rrggbb` / `#rgb` - 24-bit truecolor
+//! - `rgb(r,g,b)` - 24-bit truecolor
+//! - `color(n)` - 256-color palette index
+//!
+//! A color prefixed with `bg:` or `on_` sets the background instead of the
+//! foreground. Colors are downsampled (truecolor → 256 → 16) to the
+//! terminal's color depth.
+//!
+//! Names from the `[styles]` palette (`warn = "red,bold"`) can be used
+//! anywhere a part can; [`resolve`] expands them before parsing, so palette
+//! names take precedence over the built-in ones.
+//!
+//! The [`LINK`] part adds no SGR code: it asks the renderer to make the
+//! value a hyperlink (see `hyperlink.rs`).
+//!
+//! Both the renderer and `--validate` go through [`Style::parse`], so they
+//! always agree on what a valid style is.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Named styles from the `[styles]` table and themes: name -> style spec
+pub type Palette = HashMap<String, String>;
+
+/// Style part that turns a value into a hyperlink
+pub const LINK: &str = "link";
+
+/// How many colors the terminal can display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ColorDepth {
+    /// 16 ANSI colors
+    #[serde(rename = "16")]
+    Ansi16,
+    /// 256-color palette
+    #[serde(rename = "256")]
+    Palette256,
+    /// 24-bit RGB
+    #[serde(rename = "truecolor", alias = "24bit")]
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Detect color depth from the environment
+    ///
+    /// - `COLORTERM=truecolor` or `COLORTERM=24bit` → truecolor
+    /// - `TERM` containing `256color` → 256
+    /// - anything else → 16
+    pub fn detect() -> Self {
+        Self::from_env(
+            std::env::var("COLORTERM").ok().as_deref(),
+            std::env::var("TERM").ok().as_deref(),
+        )
+    }
+
+    fn from_env(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        if matches!(colorterm, Some("truecolor") | Some("24bit")) {
+            ColorDepth::TrueColor
+        } else if term.map(|t| t.contains("256color")).unwrap_or(false) {
+            ColorDepth::Palette256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
+/// Text attributes and their SGR codes
+///
+/// This is the single source of truth for attribute names: rendering and
+/// validation both look names up here.
+const ATTRIBUTES: [(&str, u8); 9] = [
+    ("normal", 0),
+    ("bold", 1),
+    ("dim", 2),
+    ("italic", 3),
+    ("underline", 4),
+    ("blink", 5),
+    ("reverse", 7),
+    ("strikethrough", 9),
+    ("overline", 53),
+];
+
+/// Look up the SGR code of a text attribute
+fn attribute_code(name: &str) -> Option<u8> {
+    ATTRIBUTES.iter().find(|(n, _)| *n == name).map(|(_, code)| *code)
+}
+
+/// Split a style string from the config file (`"rgb(1, 2, 3),bold"`) into parts
+///
+/// Commas inside parentheses belong to the part; whitespace is dropped, the
+/// same normalization the template parser applies to `{var:...}` styles.
+pub fn split_spec(spec: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut depth = 0usize;
+
+    for c in spec.chars() {
+        match c {
+            ',' if depth == 0 => parts.push(std::mem::take(&mut part)),
+            c if c.is_whitespace() => {}
+            _ => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                part.push(c);
+            }
+        }
+    }
+    parts.push(part);
+
+    parts.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+/// Expand palette names in style parts
+///
+/// Entries may reference other entries (`warn = "alert,bold"`). A name that
+/// refers to itself while being expanded falls back to the built-in meaning,
+/// so `red = "red,bold"` works and cycles end. `bg:<name>` works for entries
+/// that are a single color.
+pub fn resolve<S: AsRef<str>>(parts: &[S], palette: &Palette) -> Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    let mut errors = Vec::new();
+    for part in parts {
+        expand(part.as_ref(), palette, &mut Vec::new(), &mut out, &mut errors);
+    }
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(out),
+    }
+}
+
+/// Expand palette names, keeping parts that cannot be expanded as-is
+///
+/// Used when rendering; `twig --validate` reports the problem.
+pub fn resolve_lossy<S: AsRef<str>>(parts: &[S], palette: &Palette) -> Vec<String> {
+    let mut out = Vec::new();
+    for part in parts {
+        expand(part.as_ref(), palette, &mut Vec::new(), &mut out, &mut Vec::new());
+    }
+    out
+}
+
+/// Recursively expand one part; `active` holds the names being expanded
+fn expand(
+    part: &str,
+    palette: &Palette,
+    active: &mut Vec<String>,
+    out: &mut Vec<String>,
+    errors: &mut Vec<String>,
+) {
+    // `on_<name>` is a background like `bg:<name>`, unless the palette has the whole name
+    let on = part.strip_prefix("on_").filter(|_| !palette.contains_key(part));
+    let (background, name) = match part.strip_prefix("bg:").or(on) {
+        Some(name) => (true, name),
+        None => (false, part.strip_prefix("fg:").unwrap_or(part)),
+    };
+
+    let spec = match palette.get(name) {
+        Some(spec) if !active.iter().any(|a| a == name) => spec,
+        _ => {
+            out.push(part.to_string());
+            return;
+        }
+    };
+
+    let mut expanded = Vec::new();
+    active.push(name.to_string());
+    for inner in split_spec(spec) {
+        expand(&inner, palette, active, &mut expanded, errors);
+    }
+    active.pop();
+
+    if !background {
+        out.extend(expanded);
+        return;
+    }
+    match expanded.as_slice() {
+        [color] if attribute_code(color).is_none() && !color.starts_with("bg:") => {
+            out.push(format!("bg:{}", color.strip_prefix("fg:").unwrap_or(color)));
+        }
+        _ => {
+            errors.push(format!(
+                "Style '{}' cannot be used as a background (it must be a single color)",
+                name
+            ));
+            out.push(part.to_string());
+        }
+    }
+}
+
+/// A parsed style: optional foreground/background colors plus attributes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub attributes: Vec<u8>,
+}
+
+impl Style {
+    /// Parse style parts, failing on the first unknown or malformed part
+    pub fn parse<S: AsRef<str>>(parts: &[S]) -> Result<Style, String> {
+        let mut style = Style::default();
+        for part in parts {
+            style.apply(part.as_ref())?;
+        }
+        Ok(style)
+    }
+
+    /// Parse style parts, skipping unknown or malformed ones
+    ///
+    /// Used when rendering so a typo never breaks the prompt;
+    /// `twig --validate` reports the problem.
+    pub fn parse_lossy<S: AsRef<str>>(parts: &[S]) -> Style {
+        let mut style = Style::default();
+        for part in parts {
+            let _ = style.apply(part.as_ref());
+        }
+        style
+    }
+
+    /// Apply a single style part (later parts override earlier colors)
+    fn apply(&mut self, part: &str) -> Result<(), String> {
+        if part == LINK {
+            // Handled by the renderer
+            return Ok(());
+        }
+
+        if let Some(code) = attribute_code(part) {
+            self.attributes.push(code);
+            return Ok(());
+        }
+
+        // Background: bg:<color> or on_<named color>
+        let background = part.strip_prefix("bg:").or_else(|| part.strip_prefix("on_"));
+        if let Some(spec) = background {
+            return match Color::parse(spec)? {
+                Some(color) => {
+                    self.bg = Some(color);
+                    Ok(())
+                }
+                None => Err(format!("Unknown background color: '{}'", part)),
+            };
+        }
+
+        let spec = part.strip_prefix("fg:").unwrap_or(part);
+        match Color::parse(spec)? {
+            Some(color) => {
+                self.fg = Some(color);
+                Ok(())
+            }
+            None => Err(format!("Unknown color or style: '{}'", part)),
+        }
+    }
+
+    /// SGR parameters for this style, e.g. `38;2;136;192;208;48;5;236;1`
+    ///
+    /// Returns None if the style sets nothing.
+    pub fn sgr_params(&self, depth: ColorDepth) -> Option<String> {
+        let mut codes = Vec::new();
+        if let Some(fg) = self.fg {
+            codes.push(fg.fg_code(depth));
+        }
+        if let Some(bg) = self.bg {
+            codes.push(bg.bg_code(depth));
+        }
+        codes.extend(self.attributes.iter().map(|code| code.to_string()));
+
+        if codes.is_empty() {
+            None
+        } else {
+            Some(codes.join(";"))
+        }
+    }
+}
+
+/// A foreground or background color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// One of the 16 ANSI colors (0-7 normal, 8-15 bright)
+    Ansi(u8),
+    /// 256-color palette index
+    Palette(u8),
+    /// 24-bit RGB
+    Rgb(u8, u8, u8),
+}
+
+/// Named colors in ANSI order (index = color number)
+const COLOR_NAMES: [&str; 16] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    "bright_black", "bright_red", "bright_green", "bright_yellow",
+    "bright_blue", "bright_magenta", "bright_cyan", "bright_white",
+];
+
+/// xterm's default RGB values for the 16 ANSI colors, used for downsampling
+const ANSI_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+/// Intensity levels of the 6x6x6 color cube in the 256-color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl Color {
+    /// Parse a color spec
+    ///
+    /// Returns `Ok(None)` if `spec` is not a color at all (e.g. a style like
+    /// `bold`), and `Err` if it looks like a color but is malformed.
+    pub fn parse(spec: &str) -> Result<Option<Color>, String> {
+        if let Some(index) = Self::named(spec) {
+            return Ok(Some(Color::Ansi(index)));
+        }
+
+        if let Some(hex) = spec.strip_prefix('#') {
+            return Self::parse_hex(hex)
+                .map(Some)
+                .ok_or_else(|| format!("Invalid hex color '{}' (expected #rrggbb or #rgb)", spec));
+        }
+
+        if let Some(args) = function_args(spec, "rgb") {
+            let values: Vec<Option<u8>> = args.split(',').map(|v| v.trim().parse().ok()).collect();
+            return match values.as_slice() {
+                [Some(r), Some(g), Some(b)] => Ok(Some(Color::Rgb(*r, *g, *b))),
+                _ => Err(format!("Invalid color '{}' (expected rgb(r,g,b) with values 0-255)", spec)),
+            };
+        }
+
+        if let Some(args) = function_args(spec, "color") {
+            return args
+                .trim()
+                .parse()
+                .map(|n| Some(Color::Palette(n)))
+                .map_err(|_| format!("Invalid color '{}' (expected color(n) with n 0-255)", spec));
+        }
+
+        Ok(None)
+    }
+
+    /// Look up a named color (`gray`/`grey` are aliases for `bright_black`)
+    fn named(name: &str) -> Option<u8> {
+        match name {
+            "gray" | "grey" => Some(8),
+            _ => COLOR_NAMES.iter().position(|n| *n == name).map(|i| i as u8),
+        }
+    }
+
+    fn parse_hex(hex: &str) -> Option<Color> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+        match hex.len() {
+            6 => Some(Color::Rgb(channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?)),
+            // #rgb shorthand: each digit is doubled (#abc = #aabbcc)
+            3 => {
+                let short = |i: usize| channel(&hex[i..i + 1]).map(|v| v * 17);
+                Some(Color::Rgb(short(0)?, short(1)?, short(2)?))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reduce this color to what the terminal can display
+    pub fn downsample(self, depth: ColorDepth) -> Color {
+        match (self, depth) {
+            (Color::Rgb(r, g, b), ColorDepth::Palette256) => Color::Palette(rgb_to_palette(r, g, b)),
+            (Color::Rgb(r, g, b), ColorDepth::Ansi16) => Color::Ansi(nearest_ansi(r, g, b)),
+            (Color::Palette(n), ColorDepth::Ansi16) => {
+                let (r, g, b) = palette_to_rgb(n);
+                Color::Ansi(nearest_ansi(r, g, b))
+            }
+            (color, _) => color,
+        }
+    }
+
+    /// SGR parameters for this color as a foreground color at the given depth
+    pub fn fg_code(self, depth: ColorDepth) -> String {
+        match self.downsample(depth) {
+            Color::Ansi(n) if n < 8 => (30 + n).to_string(),
+            Color::Ansi(n) => (90 + n - 8).to_string(),
+            Color::Palette(n) => format!("38;5;{}", n),
+            Color::Rgb(r, g, b) => format!("38;2;{};{};{}", r, g, b),
+        }
+    }
+
+    /// SGR parameters for this color as a background color at the given depth
+    pub fn bg_code(self, depth: ColorDepth) -> String {
+        match self.downsample(depth) {
+            Color::Ansi(n) if n < 8 => (40 + n).to_string(),
+            Color::Ansi(n) => (100 + n - 8).to_string(),
+            Color::Palette(n) => format!("48;5;{}", n),
+            Color::Rgb(r, g, b) => format!("48;2;{};{};{}", r, g, b),
+        }
+    }
+}
+
+/// If `spec` is `name(...)`, return the text between the parentheses
+fn function_args<'a>(spec: &'a str, name: &str) -> Option<&'a str> {
+    spec.strip_prefix(name)?.strip_prefix('(')?.strip_suffix(')')
+}
+
+/// Map an RGB color to the closest entry of the 256-color palette
+/// (either the 6x6x6 cube or the 24-step grayscale ramp)
+fn rgb_to_palette(r: u8, g: u8, b: u8) -> u8 {
+    let cube_index = |v: u8| -> usize {
+        CUBE_LEVELS
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, level)| (**level as i32 - v as i32).abs())
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    };
+    let (ri, gi, bi) = (cube_index(r), cube_index(g), cube_index(b));
+    let cube = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+
+    // Grayscale ramp: 232-255 covers 8, 18, ..., 238
+    let avg = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_index = ((avg.saturating_sub(3)) / 10).min(23) as u8;
+    let gray_level = 8 + gray_index * 10;
+
+    if distance((r, g, b), (gray_level, gray_level, gray_level)) < distance((r, g, b), cube) {
+        232 + gray_index
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
+
+/// RGB value of a 256-color palette entry
+fn palette_to_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => ANSI_RGB[n as usize],
+        16..=231 => {
+            let i = n - 16;
+            (
+                CUBE_LEVELS[(i / 36) as usize],
+                CUBE_LEVELS[((i / 6) % 6) as usize],
+                CUBE_LEVELS[(i % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + (n - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+/// Index of the ANSI color closest to an RGB value
+fn nearest_ansi(r: u8, g: u8, b: u8) -> u8 {
+    ANSI_RGB
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, rgb)| distance((r, g, b), **rgb))
+        .map(|(i, _)| i as u8)
+        .unwrap_or(7)
+}
+
+/// Squared euclidean distance between two RGB colors
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette(pairs: &[(&str, &str)]) -> Palette {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_resolve_palette() {
+        let palette = palette(&[
+            ("warn", "red,bold"),
+            ("vcs", "#b48ead"),
+            ("alert", "warn,underline"),
+            ("red", "red,bold"),
+            ("a", "b"),
+            ("b", "a"),
+        ]);
+
+        assert_eq!(resolve(&["warn"], &palette).unwrap(), vec!["red", "bold", "bold"]);
+        assert_eq!(resolve(&["vcs", "italic"], &palette).unwrap(), vec!["#b48ead", "italic"]);
+        // Nested names
+        assert_eq!(resolve(&["alert"], &palette).unwrap(), vec!["red", "bold", "bold", "underline"]);
+        // Unknown names pass through for Style::parse to judge
+        assert_eq!(resolve(&["green"], &palette).unwrap(), vec!["green"]);
+        // Cycles end at the built-in meaning
+        assert_eq!(resolve(&["a"], &palette).unwrap(), vec!["a"]);
+        // Background
+        assert_eq!(resolve(&["bg:vcs"], &palette).unwrap(), vec!["bg:#b48ead"]);
+        assert!(resolve(&["bg:warn"], &palette).unwrap_err().contains("background"));
+        assert_eq!(resolve_lossy(&["bg:warn", "vcs"], &palette), vec!["bg:warn", "#b48ead"]);
+        // on_ is the other background spelling
+        assert_eq!(resolve(&["on_vcs"], &palette).unwrap(), vec!["bg:#b48ead"]);
+        assert!(resolve(&["on_warn"], &palette).unwrap_err().contains("background"));
+        assert_eq!(resolve(&["on_blue"], &palette).unwrap(), vec!["on_blue"]);
+        let named_on = self::palette(&[("on_call", "yellow")]);
+        assert_eq!(resolve(&["on_call"], &named_on).unwrap(), vec!["yellow"]);
+    }
+
+    #[test]
+    fn test_split_spec() {
+        assert_eq!(split_spec("red,bold"), vec!["red", "bold"]);
+        assert_eq!(split_spec("rgb(1, 2, 3), bold"), vec!["rgb(1,2,3)", "bold"]);
+        assert_eq!(split_spec(" bg:#2e3440 ,white"), vec!["bg:#2e3440", "white"]);
+        assert!(split_spec("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_named() {
+        assert_eq!(Color::parse("red"), Ok(Some(Color::Ansi(1))));
+        assert_eq!(Color::parse("bright_white"), Ok(Some(Color::Ansi(15))));
+        assert_eq!(Color::parse("grey"), Ok(Some(Color::Ansi(8))));
+        assert_eq!(Color::parse("bold"), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_truecolor() {
+        assert_eq!(Color::parse("#88c0d0"), Ok(Some(Color::Rgb(0x88, 0xc0, 0xd0))));
+        assert_eq!(Color::parse("#fff"), Ok(Some(Color::Rgb(255, 255, 255))));
+        assert_eq!(Color::parse("rgb(180,142,173)"), Ok(Some(Color::Rgb(180, 142, 173))));
+        assert_eq!(Color::parse("rgb(1, 2, 3)"), Ok(Some(Color::Rgb(1, 2, 3))));
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        assert_eq!(Color::parse("color(208)"), Ok(Some(Color::Palette(208))));
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(Color::parse("#12345").is_err());
+        assert!(Color::parse("#gggggg").is_err());
+        assert!(Color::parse("rgb(300,0,0)").is_err());
+        assert!(Color::parse("rgb(1,2)").is_err());
+        assert!(Color::parse("color(256)").is_err());
+        assert!(Color::parse("color()").is_err());
+    }
+
+    #[test]
+    fn test_fg_code_truecolor() {
+        let color = Color::Rgb(136, 192, 208);
+        assert_eq!(color.fg_code(ColorDepth::TrueColor), "38;2;136;192;208");
+        assert_eq!(Color::Palette(208).fg_code(ColorDepth::TrueColor), "38;5;208");
+        assert_eq!(Color::Ansi(1).fg_code(ColorDepth::TrueColor), "31");
+        assert_eq!(Color::Ansi(9).fg_code(ColorDepth::TrueColor), "91");
+    }
+
+    #[test]
+    fn test_downsample_to_256() {
+        // Pure colors land on the cube corners
+        assert_eq!(Color::Rgb(255, 0, 0).fg_code(ColorDepth::Palette256), "38;5;196");
+        assert_eq!(Color::Rgb(0, 0, 0).fg_code(ColorDepth::Palette256), "38;5;16");
+        // Mid gray uses the grayscale ramp
+        assert_eq!(Color::Rgb(128, 128, 128).fg_code(ColorDepth::Palette256), "38;5;244");
+        // Palette colors are unchanged
+        assert_eq!(Color::Palette(208).fg_code(ColorDepth::Palette256), "38;5;208");
+    }
+
+    #[test]
+    fn test_downsample_to_16() {
+        assert_eq!(Color::Rgb(250, 10, 10).fg_code(ColorDepth::Ansi16), "91");
+        assert_eq!(Color::Rgb(0, 0, 230).fg_code(ColorDepth::Ansi16), "34");
+        // color(208) is orange (255,135,0) - closest to red or yellow
+        let code = Color::Palette(208).fg_code(ColorDepth::Ansi16);
+        assert!(["31", "33", "91", "93"].contains(&code.as_str()), "got {}", code);
+        // Low palette indexes are the ANSI colors themselves
+        assert_eq!(Color::Palette(4).fg_code(ColorDepth::Ansi16), "34");
+    }
+
+    #[test]
+    fn test_bg_code() {
+        assert_eq!(Color::Ansi(4).bg_code(ColorDepth::TrueColor), "44");
+        assert_eq!(Color::Ansi(8).bg_code(ColorDepth::TrueColor), "100");
+        assert_eq!(Color::Palette(236).bg_code(ColorDepth::TrueColor), "48;5;236");
+        assert_eq!(Color::Rgb(46, 52, 64).bg_code(ColorDepth::TrueColor), "48;2;46;52;64");
+    }
+
+    #[test]
+    fn test_style_parse() {
+        let style = Style::parse(&["white", "bg:blue", "bold", "strikethrough"]).unwrap();
+        assert_eq!(style.fg, Some(Color::Ansi(7)));
+        assert_eq!(style.bg, Some(Color::Ansi(4)));
+        assert_eq!(style.attributes, vec![1, 9]);
+        assert_eq!(style.sgr_params(ColorDepth::TrueColor), Some("37;44;1;9".to_string()));
+
+        let style = Style::parse(&["on_bright_black"]).unwrap();
+        assert_eq!(style.bg, Some(Color::Ansi(8)));
+
+        let style = Style::parse(&["bg:#2e3440", "fg:#88c0d0"]).unwrap();
+        assert_eq!(
+            style.sgr_params(ColorDepth::TrueColor),
+            Some("38;2;136;192;208;48;2;46;52;64".to_string())
+        );
+    }
+
+    #[test]
+    fn test_style_attributes() {
+        for (name, code) in [
+            ("dim", "2"),
+            ("blink", "5"),
+            ("reverse", "7"),
+            ("strikethrough", "9"),
+            ("overline", "53"),
+        ] {
+            let style = Style::parse(&[name]).unwrap();
+            assert_eq!(style.sgr_params(ColorDepth::TrueColor), Some(code.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_style_parse_errors() {
+        assert_eq!(
+            Style::parse(&["purple"]).unwrap_err(),
+            "Unknown color or style: 'purple'"
+        );
+        assert_eq!(
+            Style::parse(&["bg:bold"]).unwrap_err(),
+            "Unknown background color: 'bg:bold'"
+        );
+        assert!(Style::parse(&["on_#12"]).is_err());
+    }
+
+    #[test]
+    fn test_style_parse_lossy() {
+        let style = Style::parse_lossy(&["purple", "red", "bg:nope", "bold"]);
+        assert_eq!(style.sgr_params(ColorDepth::TrueColor), Some("31;1".to_string()));
+        assert_eq!(Style::parse_lossy(&["purple"]).sgr_params(ColorDepth::TrueColor), None);
+    }
+
+    #[test]
+    fn test_link_has_no_sgr() {
+        let style = Style::parse(&["cyan", LINK]).unwrap();
+        assert_eq!(style.sgr_params(ColorDepth::TrueColor), Some("36".to_string()));
+        assert_eq!(Style::parse(&[LINK]).unwrap().sgr_params(ColorDepth::TrueColor), None);
+    }
+
+    #[test]
+    fn test_detect_depth() {
+        assert_eq!(ColorDepth::from_env(Some("truecolor"), Some("xterm")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::from_env(Some("24bit"), None), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::from_env(None, Some("xterm-256color")), ColorDepth::Palette256);
+        assert_eq!(ColorDepth::from_env(None, Some("xterm")), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::from_env(None, None), ColorDepth::Ansi16);
+    }
+}
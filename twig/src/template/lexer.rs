@@ -0,0 +1,484 @@
+// twig/src/template/lexer.rs
+
+use super::{Span, TemplateError};
+
+/// A single lexical token from a format string
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// Plain text outside of `{...}` (escapes already resolved)
+    Text(String),
+    /// `~` - conditional space marker
+    Tilde,
+    /// `[` - start of an optional group
+    GroupOpen,
+    /// `]` - end of an optional group
+    GroupClose,
+    /// `{` - start of a placeholder
+    Open,
+    /// `}` - end of a placeholder
+    Close,
+    /// `:` - separates a placeholder source from its style
+    Colon,
+    /// `|` - starts a filter, e.g. `{cwd|basename}`
+    Pipe,
+    /// `?` - starts a fallback, e.g. `{git_branch?"no-repo"}`
+    Question,
+    /// `!` - starts a priority, e.g. `{ip_address:yellow!low}`
+    Bang,
+    /// `,` - separates style parts and function arguments
+    Comma,
+    /// `(` - start of function arguments, e.g. `rgb(...)`
+    LParen,
+    /// `)` - end of function arguments
+    RParen,
+    /// `"..."` - quoted literal inside a placeholder (quotes stripped)
+    Str(String),
+    /// Bare word inside a placeholder (variable name, $ENV name, color, style)
+    Word(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Split a format string into tokens
+///
+/// The lexer has two modes:
+/// - Outside braces everything is text, except `~` (conditional space),
+///   `[`/`]` (optional group) and `{` (start of a placeholder).
+/// - Inside braces whitespace is skipped and the input is split into quoted
+///   strings, punctuation (`:` `|` `?` `!` `,` `(` `)` `}`) and bare words.
+///
+/// Escapes (see [`lex_escape`]) are resolved in text and in quoted strings.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    // Pending text run (outside braces) and where it started
+    let mut text = String::new();
+    let mut text_start = 0;
+
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if text.is_empty() {
+                    text_start = pos;
+                }
+                text.push(lex_escape(source, pos, &mut chars)?.unwrap_or('\\'));
+            }
+            '~' | '[' | ']' => {
+                flush_text(&mut tokens, &mut text, text_start, pos);
+                let kind = match c {
+                    '~' => TokenKind::Tilde,
+                    '[' => TokenKind::GroupOpen,
+                    _ => TokenKind::GroupClose,
+                };
+                tokens.push(Token {
+                    kind,
+                    span: Span::new(pos, pos + 1),
+                });
+            }
+            '{' => {
+                flush_text(&mut tokens, &mut text, text_start, pos);
+                tokens.push(Token {
+                    kind: TokenKind::Open,
+                    span: Span::new(pos, pos + 1),
+                });
+                lex_placeholder(source, pos, &mut chars, &mut tokens)?;
+            }
+            _ => {
+                if text.is_empty() {
+                    text_start = pos;
+                }
+                text.push(c);
+            }
+        }
+    }
+
+    flush_text(&mut tokens, &mut text, text_start, source.len());
+    Ok(tokens)
+}
+
+/// Push the pending text run as a Text token (if any)
+fn flush_text(tokens: &mut Vec<Token>, text: &mut String, start: usize, end: usize) {
+    if !text.is_empty() {
+        tokens.push(Token {
+            kind: TokenKind::Text(std::mem::take(text)),
+            span: Span::new(start, end),
+        });
+    }
+}
+
+/// Lex the inside of a placeholder, up to and including the closing `}`
+fn lex_placeholder(
+    source: &str,
+    open: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    tokens: &mut Vec<Token>,
+) -> Result<(), TemplateError> {
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '}' => {
+                tokens.push(Token {
+                    kind: TokenKind::Close,
+                    span: Span::new(pos, pos + 1),
+                });
+                return Ok(());
+            }
+            ':' => tokens.push(Token {
+                kind: TokenKind::Colon,
+                span: Span::new(pos, pos + 1),
+            }),
+            '|' => tokens.push(Token {
+                kind: TokenKind::Pipe,
+                span: Span::new(pos, pos + 1),
+            }),
+            '?' => tokens.push(Token {
+                kind: TokenKind::Question,
+                span: Span::new(pos, pos + 1),
+            }),
+            '!' => tokens.push(Token {
+                kind: TokenKind::Bang,
+                span: Span::new(pos, pos + 1),
+            }),
+            ',' => tokens.push(Token {
+                kind: TokenKind::Comma,
+                span: Span::new(pos, pos + 1),
+            }),
+            '(' => tokens.push(Token {
+                kind: TokenKind::LParen,
+                span: Span::new(pos, pos + 1),
+            }),
+            ')' => tokens.push(Token {
+                kind: TokenKind::RParen,
+                span: Span::new(pos, pos + 1),
+            }),
+            '{' => {
+                return Err(TemplateError::new(
+                    "unexpected '{' inside placeholder",
+                    Span::new(pos, pos + 1),
+                    source,
+                ));
+            }
+            '"' => {
+                let mut value = String::new();
+                let mut end = None;
+                while let Some((p, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            end = Some(p + 1);
+                            break;
+                        }
+                        '\\' => value.push(lex_escape(source, p, chars)?.unwrap_or('\\')),
+                        _ => value.push(c),
+                    }
+                }
+                match end {
+                    Some(end) => tokens.push(Token {
+                        kind: TokenKind::Str(value),
+                        span: Span::new(pos, end),
+                    }),
+                    None => {
+                        return Err(TemplateError::new(
+                            "unterminated string literal",
+                            Span::new(pos, source.len()),
+                            source,
+                        ));
+                    }
+                }
+            }
+            _ => {
+                let mut word = String::from(c);
+                let mut end = pos + c.len_utf8();
+                while let Some(&(p, c)) = chars.peek() {
+                    if is_delimiter(c) {
+                        break;
+                    }
+                    word.push(c);
+                    end = p + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Word(word),
+                    span: Span::new(pos, end),
+                });
+            }
+        }
+    }
+
+    Err(TemplateError::new(
+        "unclosed '{'",
+        Span::new(open, source.len()),
+        source,
+    ))
+}
+
+/// Resolve the escape sequence following a backslash at `backslash`
+///
+/// | Escape                          | Result                    |
+/// |---------------------------------|---------------------------|
+/// | `\{` `\}` `\"` `\\` `\~` `\[` `\]`   | The character itself      |
+/// | `\t` / `\n`                     | Tab / newline             |
+/// | `\e`                            | Escape (`\x1b`)           |
+/// | `\u{2192}`                      | Unicode code point (hex)  |
+///
+/// Returns `None` for any other character: the backslash is kept as-is and
+/// the character is lexed normally, so `C:\Users` stays unchanged.
+fn lex_escape(
+    source: &str,
+    backslash: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<Option<char>, TemplateError> {
+    let Some(&(_, c)) = chars.peek() else {
+        return Ok(None);
+    };
+    let escaped = match c {
+        '{' | '}' | '"' | '\\' | '~' | '[' | ']' => c,
+        't' => '\t',
+        'n' => '\n',
+        'e' => '\x1b',
+        'u' => {
+            // Only `\u{...}` is an escape
+            let mut ahead = chars.clone();
+            ahead.next();
+            if !matches!(ahead.peek(), Some((_, '{'))) {
+                return Ok(None);
+            }
+            chars.next();
+            chars.next();
+            return lex_unicode(source, backslash, chars).map(Some);
+        }
+        _ => return Ok(None),
+    };
+    chars.next();
+    Ok(Some(escaped))
+}
+
+/// Parse the `XXXX}` of a `\u{XXXX}` escape
+fn lex_unicode(
+    source: &str,
+    backslash: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<char, TemplateError> {
+    let error = |end: usize| {
+        TemplateError::new(
+            "invalid unicode escape (expected \\u{XXXX} with 1-6 hex digits)",
+            Span::new(backslash, end),
+            source,
+        )
+    };
+
+    let mut hex = String::new();
+    for (p, c) in chars.by_ref() {
+        if c == '}' {
+            return match u32::from_str_radix(&hex, 16) {
+                Ok(code) if hex.len() <= 6 => char::from_u32(code).ok_or_else(|| error(p + 1)),
+                _ => Err(error(p + 1)),
+            };
+        }
+        if !c.is_ascii_hexdigit() {
+            return Err(error(p + c.len_utf8()));
+        }
+        hex.push(c);
+    }
+    Err(error(source.len()))
+}
+
+/// Characters that end a bare word inside a placeholder
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '{' | '}' | ':' | '|' | '?' | '!' | ',' | '(' | ')' | '"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize_text_and_placeholder() {
+        assert_eq!(
+            kinds("a {cwd:green,bold} b"),
+            vec![
+                TokenKind::Text("a ".to_string()),
+                TokenKind::Open,
+                TokenKind::Word("cwd".to_string()),
+                TokenKind::Colon,
+                TokenKind::Word("green".to_string()),
+                TokenKind::Comma,
+                TokenKind::Word("bold".to_string()),
+                TokenKind::Close,
+                TokenKind::Text(" b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_string_with_delimiters() {
+        // Braces and colons inside quotes belong to the string
+        assert_eq!(
+            kinds("{\"}:{\":red}"),
+            vec![
+                TokenKind::Open,
+                TokenKind::Str("}:{".to_string()),
+                TokenKind::Colon,
+                TokenKind::Word("red".to_string()),
+                TokenKind::Close,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_function_style() {
+        assert_eq!(
+            kinds("{x:rgb(1, 2,3)}"),
+            vec![
+                TokenKind::Open,
+                TokenKind::Word("x".to_string()),
+                TokenKind::Colon,
+                TokenKind::Word("rgb".to_string()),
+                TokenKind::LParen,
+                TokenKind::Word("1".to_string()),
+                TokenKind::Comma,
+                TokenKind::Word("2".to_string()),
+                TokenKind::Comma,
+                TokenKind::Word("3".to_string()),
+                TokenKind::RParen,
+                TokenKind::Close,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_filters() {
+        assert_eq!(
+            kinds("{$USER|truncate(3,\"…\")}"),
+            vec![
+                TokenKind::Open,
+                TokenKind::Word("$USER".to_string()),
+                TokenKind::Pipe,
+                TokenKind::Word("truncate".to_string()),
+                TokenKind::LParen,
+                TokenKind::Word("3".to_string()),
+                TokenKind::Comma,
+                TokenKind::Str("…".to_string()),
+                TokenKind::RParen,
+                TokenKind::Close,
+            ]
+        );
+        // Outside braces a pipe is plain text
+        assert_eq!(kinds("a|b"), vec![TokenKind::Text("a|b".to_string())]);
+    }
+
+    #[test]
+    fn test_tokenize_priority() {
+        assert_eq!(
+            kinds("{ip:yellow!low}!"),
+            vec![
+                TokenKind::Open,
+                TokenKind::Word("ip".to_string()),
+                TokenKind::Colon,
+                TokenKind::Word("yellow".to_string()),
+                TokenKind::Bang,
+                TokenKind::Word("low".to_string()),
+                TokenKind::Close,
+                // Outside braces `!` is plain text
+                TokenKind::Text("!".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_fallbacks() {
+        assert_eq!(
+            kinds("{ip_address?\"a?b\"}"),
+            vec![
+                TokenKind::Open,
+                TokenKind::Word("ip_address".to_string()),
+                TokenKind::Question,
+                TokenKind::Str("a?b".to_string()),
+                TokenKind::Close,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_tilde() {
+        assert_eq!(
+            kinds("a~b\\~c"),
+            vec![
+                TokenKind::Text("a".to_string()),
+                TokenKind::Tilde,
+                TokenKind::Text("b~c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_groups() {
+        assert_eq!(
+            kinds("[ on {x}]\\[y\\]"),
+            vec![
+                TokenKind::GroupOpen,
+                TokenKind::Text(" on ".to_string()),
+                TokenKind::Open,
+                TokenKind::Word("x".to_string()),
+                TokenKind::Close,
+                TokenKind::GroupClose,
+                TokenKind::Text("[y]".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_escapes() {
+        assert_eq!(
+            kinds("\\{\"json\": 1\\} \\\\ \\t\\u{2192}"),
+            vec![TokenKind::Text("{\"json\": 1} \\ \t\u{2192}".to_string())]
+        );
+        // Inside quoted strings
+        assert_eq!(
+            kinds("{\"say \\\"hi\\\" \\e[1m\\\\\"}"),
+            vec![
+                TokenKind::Open,
+                TokenKind::Str("say \"hi\" \x1b[1m\\".to_string()),
+                TokenKind::Close,
+            ]
+        );
+        // Unknown escapes keep their backslash
+        assert_eq!(kinds("C:\\Users\\me\\"), vec![TokenKind::Text("C:\\Users\\me\\".to_string())]);
+        assert_eq!(kinds("{\"a\\d\"}")[1], TokenKind::Str("a\\d".to_string()));
+        // Escaped text keeps its source span
+        assert_eq!(tokenize("\\{x").unwrap()[0].span, Span::new(0, 3));
+    }
+
+    #[test]
+    fn test_tokenize_unicode_escape_errors() {
+        for source in ["\\u{}", "\\u{110000}", "\\u{12g4}", "{\"\\u{2192\"}", "\\u{1234567}"] {
+            let err = tokenize(source).unwrap_err();
+            assert!(err.message.starts_with("invalid unicode escape"), "{}: {}", source, err.message);
+            assert_eq!(err.span.start, source.find('\\').unwrap());
+        }
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let tokens = tokenize("ab{cwd}").unwrap();
+        assert_eq!(tokens[0].span, Span::new(0, 2));
+        assert_eq!(tokens[1].span, Span::new(2, 3));
+        assert_eq!(tokens[2].span, Span::new(3, 6));
+        assert_eq!(tokens[3].span, Span::new(6, 7));
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        assert!(tokenize("{cwd").is_err());
+        assert!(tokenize("{\"abc}").is_err());
+        assert!(tokenize("{cwd{time}}").is_err());
+    }
+}
@@ -0,0 +1,705 @@
+// twig/src/template/mod.rs
+
+//! Format string parsing
+//!
+//! A format string such as `{time:cyan} {"@":yellow}~{git_branch|truncate(20)}` is parsed
+//! once into a [`Template`]: a tree of [`Node`]s with source spans (optional
+//! `[...]` groups nest, everything else is flat).
+//! Rendering, provider discovery, implicit sections and `--validate` all work
+//! on this AST instead of re-scanning the string.
+
+mod lexer;
+mod parser;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Named template fragments from `[fragments]`, referenced as `{@name}`
+pub type Fragments = HashMap<String, String>;
+
+/// Byte range in the original format string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+/// Where a placeholder's text comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// `{"text"}` - literal text
+    Literal(String),
+    /// `{name}` - variable provided by a provider
+    Variable(String),
+    /// `{$NAME}` - environment variable (name stored without the `$`)
+    Env(String),
+}
+
+/// Style list after the `:` in a placeholder, e.g. `cyan,bold` or `bg:#2e3440,white`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleSpec {
+    pub parts: Vec<String>,
+    pub span: Span,
+}
+
+/// How readily a placeholder or group is dropped when the prompt is too
+/// wide: `{ip_address:yellow!low}`, `[ on {git_branch}]!2`
+///
+/// Lower priorities are dropped first; parts without one are never dropped.
+pub type Priority = u8;
+
+/// Named priorities, usable wherever a number is
+const PRIORITY_NAMES: [(&str, Priority); 3] = [("low", 1), ("medium", 2), ("high", 3)];
+
+/// Parse a priority: `low`, `medium`, `high` or a number from 0 to 255
+pub fn parse_priority(word: &str) -> Option<Priority> {
+    PRIORITY_NAMES
+        .iter()
+        .find(|(name, _)| *name == word)
+        .map(|(_, priority)| *priority)
+        .or_else(|| word.parse().ok())
+}
+
+/// A `|name(args)` filter applied to a placeholder's value, e.g. `|truncate(20,"…")`
+///
+/// Names are not checked while parsing; see [`crate::filter`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    pub args: Vec<String>,
+    pub span: Span,
+}
+
+/// A `{...}` placeholder
+///
+/// `{git_branch:magenta?"no-repo":bright_black}` is a placeholder for
+/// `git_branch` with one fallback. Fallbacks are placeholders themselves
+/// (with no fallbacks of their own); the first alternative with a value is shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    pub source: Source,
+    /// Filters in application order
+    pub filters: Vec<Filter>,
+    pub style: Option<StyleSpec>,
+    /// `?alternative` chain, tried in order when the source is empty
+    pub fallbacks: Vec<Placeholder>,
+    /// `!priority` after the last alternative (never set on fallbacks)
+    pub priority: Option<Priority>,
+    pub span: Span,
+}
+
+/// A node in a parsed template
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Plain text copied to the output as-is
+    Text { text: String, span: Span },
+    /// A variable, environment variable or literal, optionally styled
+    Placeholder(Placeholder),
+    /// `~` - a space that only appears if the following variable has a value
+    ConditionalSpace { span: Span },
+    /// `[...]` - optional group, dropped entirely (literals and styling included)
+    /// unless at least one variable inside it has a value
+    Group {
+        nodes: Vec<Node>,
+        /// `[...]!priority`
+        priority: Option<Priority>,
+        span: Span,
+    },
+    /// `{fill:"─":bright_black}` - repeats `text` to pad its line to the terminal width
+    Fill {
+        text: String,
+        style: Option<StyleSpec>,
+        span: Span,
+    },
+}
+
+impl Node {
+    /// Whether this node is whitespace-only text
+    ///
+    /// Conditional spaces look past such nodes to find their variable.
+    pub fn is_blank(&self) -> bool {
+        matches!(self, Node::Text { text, .. } if text.trim().is_empty())
+    }
+}
+
+/// Error produced when a format string cannot be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateError {
+    pub message: String,
+    pub span: Span,
+    pub line: usize,
+    pub column: usize,
+    /// Fragment the error was found in, when it comes from a `{@name}` reference
+    /// (the message already names the fragment)
+    pub fragment: Option<String>,
+}
+
+impl TemplateError {
+    pub fn new(message: &str, span: Span, source: &str) -> Self {
+        // Line and column are 1-based and counted in characters
+        let before = &source[..span.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+
+        Self {
+            message: message.to_string(),
+            span,
+            line,
+            column,
+            fragment: None,
+        }
+    }
+
+    /// Mark the error as found in fragment `name`
+    pub fn in_fragment(mut self, name: &str) -> Self {
+        self.fragment = Some(name.to_string());
+        self
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+
+/// A parsed format string (the default template is empty)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    /// Parse a format string, expanding `{@name}` references to `fragments`
+    ///
+    /// Fragments are spliced in as if their text had been written in place
+    /// (nodes from a fragment carry the span of the reference). Unknown
+    /// fragments and reference cycles are errors.
+    pub fn parse_with(source: &str, fragments: &Fragments) -> Result<Self, TemplateError> {
+        let tokens = lexer::tokenize(source)?;
+        let nodes = parser::Parser::new(source, tokens, fragments).parse()?;
+        Ok(Self { nodes })
+    }
+
+    /// Parse fragment `name` on its own, as it is expanded by a reference
+    ///
+    /// Used by `--validate` so that cycles are found in unused fragments too.
+    pub fn parse_fragment(name: &str, fragments: &Fragments) -> Result<Self, TemplateError> {
+        let source = fragments.get(name).map(String::as_str).unwrap_or_default();
+        let tokens = lexer::tokenize(source)?;
+        let nodes = parser::Parser::new(source, tokens, fragments).expanding(name).parse()?;
+        Ok(Self { nodes })
+    }
+
+    /// A template that renders `source` verbatim
+    ///
+    /// Used as a fallback when a format string fails to parse, so the prompt
+    /// still shows something (run `twig --validate` for the error).
+    pub fn text(source: &str) -> Self {
+        Self {
+            nodes: vec![Node::Text {
+                text: source.to_string(),
+                span: Span::new(0, source.len()),
+            }],
+        }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// All placeholders in template order, including those inside groups
+    /// and fallback alternatives (which follow their placeholder)
+    pub fn placeholders(&self) -> impl Iterator<Item = &Placeholder> {
+        let mut placeholders = Vec::new();
+        collect_placeholders(&self.nodes, &mut placeholders);
+        placeholders.into_iter()
+    }
+
+    /// Provider variable names used in the template, deduplicated, in order
+    ///
+    /// Literals and environment variables are excluded.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut vars: Vec<&str> = Vec::new();
+        for placeholder in self.placeholders() {
+            if let Source::Variable(name) = &placeholder.source {
+                if !vars.contains(&name.as_str()) {
+                    vars.push(name);
+                }
+            }
+        }
+        vars
+    }
+
+    /// All filters in template order
+    pub fn filters(&self) -> impl Iterator<Item = &Filter> {
+        self.placeholders().flat_map(|p| p.filters.iter())
+    }
+
+    /// All style specs: placeholders in template order, then fills
+    pub fn styles(&self) -> impl Iterator<Item = &StyleSpec> {
+        let mut fill_styles = Vec::new();
+        collect_fill_styles(&self.nodes, &mut fill_styles);
+        self.placeholders().filter_map(|p| p.style.as_ref()).chain(fill_styles)
+    }
+
+    /// Priorities of the placeholders and groups that have one, depth-first
+    ///
+    /// A part's index in this list identifies it for [`Self::drop_part`].
+    pub fn priorities(&self) -> Vec<Priority> {
+        let mut priorities = Vec::new();
+        collect_priorities(&self.nodes, &mut priorities);
+        priorities
+    }
+
+    /// Drop the prioritized part at `index` in [`Self::priorities`]
+    ///
+    /// The part is replaced by an empty group, so it renders like a variable
+    /// without a value: a `~` before it and a group around it collapse.
+    pub fn drop_part(&mut self, index: usize) {
+        let mut remaining = index;
+        drop_prioritized(&mut self.nodes, &mut remaining);
+    }
+}
+
+/// Priority of a placeholder or group, if it has one
+fn node_priority(node: &Node) -> Option<Priority> {
+    match node {
+        Node::Placeholder(p) => p.priority,
+        Node::Group { priority, .. } => *priority,
+        _ => None,
+    }
+}
+
+/// Depth-first collection of priorities
+fn collect_priorities(nodes: &[Node], out: &mut Vec<Priority>) {
+    for node in nodes {
+        out.extend(node_priority(node));
+        if let Node::Group { nodes, .. } = node {
+            collect_priorities(nodes, out);
+        }
+    }
+}
+
+/// Replace the prioritized node `remaining` steps ahead in depth-first order;
+/// returns whether it was found
+fn drop_prioritized(nodes: &mut [Node], remaining: &mut usize) -> bool {
+    for node in nodes {
+        if node_priority(node).is_some() {
+            if *remaining == 0 {
+                let span = match node {
+                    Node::Placeholder(p) => p.span,
+                    Node::Group { span, .. } => *span,
+                    _ => unreachable!("only placeholders and groups have priorities"),
+                };
+                *node = Node::Group {
+                    nodes: Vec::new(),
+                    priority: None,
+                    span,
+                };
+                return true;
+            }
+            *remaining -= 1;
+        }
+        if let Node::Group { nodes, .. } = node {
+            if drop_prioritized(nodes, remaining) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Depth-first collection of placeholders
+fn collect_placeholders<'a>(nodes: &'a [Node], out: &mut Vec<&'a Placeholder>) {
+    for node in nodes {
+        match node {
+            Node::Placeholder(p) => {
+                out.push(p);
+                out.extend(&p.fallbacks);
+            }
+            Node::Group { nodes, .. } => collect_placeholders(nodes, out),
+            _ => {}
+        }
+    }
+}
+
+/// Depth-first collection of `{fill}` styles
+fn collect_fill_styles<'a>(nodes: &'a [Node], out: &mut Vec<&'a StyleSpec>) {
+    for node in nodes {
+        match node {
+            Node::Fill { style: Some(style), .. } => out.push(style),
+            Node::Group { nodes, .. } => collect_fill_styles(nodes, out),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse without fragments
+    fn parse(source: &str) -> Result<Template, TemplateError> {
+        Template::parse_with(source, &Fragments::new())
+    }
+
+    fn placeholder(template: &Template, index: usize) -> &Placeholder {
+        template.placeholders().nth(index).unwrap()
+    }
+
+    #[test]
+    fn test_parse_variable_with_style() {
+        let template = parse("{cwd:green,bold}").unwrap();
+        let p = placeholder(&template, 0);
+        assert_eq!(p.source, Source::Variable("cwd".to_string()));
+        assert_eq!(p.style.as_ref().unwrap().parts, vec!["green", "bold"]);
+        assert_eq!(p.span, Span::new(0, 16));
+    }
+
+    #[test]
+    fn test_parse_literal_containing_delimiters() {
+        // Closing brace inside a literal
+        let template = parse("{\"}\":red}").unwrap();
+        let p = placeholder(&template, 0);
+        assert_eq!(p.source, Source::Literal("}".to_string()));
+        assert_eq!(p.style.as_ref().unwrap().parts, vec!["red"]);
+
+        // Colon inside a literal
+        let template = parse("{\"a:b\":red}").unwrap();
+        assert_eq!(placeholder(&template, 0).source, Source::Literal("a:b".to_string()));
+    }
+
+    #[test]
+    fn test_parse_style_functions() {
+        let template = parse("{git_branch:rgb(180, 142, 173),bold} {time:color(208)}").unwrap();
+        assert_eq!(placeholder(&template, 0).style.as_ref().unwrap().parts, vec!["rgb(180,142,173)", "bold"]);
+        assert_eq!(placeholder(&template, 1).style.as_ref().unwrap().parts, vec!["color(208)"]);
+
+        let template = parse("{cwd:#88c0d0}").unwrap();
+        assert_eq!(placeholder(&template, 0).style.as_ref().unwrap().parts, vec!["#88c0d0"]);
+
+        assert!(parse("{cwd:rgb(1,2}").is_err());
+        assert!(parse("{cwd:rgb(1,,2)}").is_err());
+    }
+
+    #[test]
+    fn test_parse_background_style() {
+        let template = parse("{cwd:bg:blue,white} {time:bg:rgb(1,2,3),on_red}").unwrap();
+        assert_eq!(placeholder(&template, 0).style.as_ref().unwrap().parts, vec!["bg:blue", "white"]);
+        assert_eq!(placeholder(&template, 1).style.as_ref().unwrap().parts, vec!["bg:rgb(1,2,3)", "on_red"]);
+
+        assert!(parse("{cwd:bg:}").is_err());
+    }
+
+    #[test]
+    fn test_parse_filters() {
+        let template = parse("{cwd|basename|upper:green,bold}").unwrap();
+        let p = placeholder(&template, 0);
+        let names: Vec<&str> = p.filters.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["basename", "upper"]);
+        assert_eq!(p.style.as_ref().unwrap().parts, vec!["green", "bold"]);
+
+        let template = parse("{git_branch|truncate(20, \"…\")} {hostname|replace(\"prod-\",\"\")}").unwrap();
+        assert_eq!(placeholder(&template, 0).filters[0].args, vec!["20", "…"]);
+        assert_eq!(placeholder(&template, 1).filters[0].args, vec!["prod-", ""]);
+        assert_eq!(placeholder(&template, 1).filters[0].span, Span::new(43, 62));
+
+        // Filters work on environment variables and literals too
+        let template = parse("{$USER|upper} {\"x\"|pad_left(3)}").unwrap();
+        assert_eq!(placeholder(&template, 0).filters[0].name, "upper");
+        assert_eq!(placeholder(&template, 1).filters[0].args, vec!["3"]);
+        assert_eq!(template.filters().count(), 2);
+
+        assert!(parse("{cwd|}").is_err());
+        assert!(parse("{cwd|truncate(}").is_err());
+        assert!(parse("{cwd:green|upper}").is_err());
+    }
+
+    #[test]
+    fn test_parse_fallbacks() {
+        let template = parse("{ip_address?ip_interface?\"offline\"}").unwrap();
+        let p = template.nodes().iter().find_map(|n| match n {
+            Node::Placeholder(p) => Some(p),
+            _ => None,
+        });
+        let p = p.unwrap();
+        assert_eq!(p.source, Source::Variable("ip_address".to_string()));
+        assert_eq!(p.span, Span::new(0, 35));
+        assert_eq!(p.fallbacks.len(), 2);
+        assert_eq!(p.fallbacks[0].source, Source::Variable("ip_interface".to_string()));
+        assert_eq!(p.fallbacks[1].source, Source::Literal("offline".to_string()));
+        assert_eq!(p.fallbacks[1].span, Span::new(25, 34));
+
+        // Fallback variables are discovered for providers
+        assert_eq!(template.variables(), vec!["ip_address", "ip_interface"]);
+
+        // Each alternative has its own filters and style
+        let template = parse("{git_branch|upper:magenta?\"no-repo\":bright_black} {$VIRTUAL_ENV?\"-\"}").unwrap();
+        let p = placeholder(&template, 0);
+        assert_eq!(p.filters[0].name, "upper");
+        assert_eq!(p.style.as_ref().unwrap().parts, vec!["magenta"]);
+        assert_eq!(p.fallbacks[0].style.as_ref().unwrap().parts, vec!["bright_black"]);
+        assert_eq!(template.styles().count(), 2);
+        assert_eq!(placeholder(&template, 2).source, Source::Env("VIRTUAL_ENV".to_string()));
+
+        assert_eq!(parse("{cwd?}").unwrap_err().message, "expected fallback after '?'");
+        assert!(parse("{cwd??\"x\"}").is_err());
+        assert!(parse("{cwd:red green}").unwrap_err().message.contains("after style"));
+    }
+
+    #[test]
+    fn test_parse_env_var() {
+        let template = parse("{$USER:yellow}").unwrap();
+        assert_eq!(placeholder(&template, 0).source, Source::Env("USER".to_string()));
+        assert!(parse("{$}").is_err());
+    }
+
+    #[test]
+    fn test_parse_conditional_space() {
+        // Before a variable: conditional space
+        let template = parse("{cwd}~{git_branch}").unwrap();
+        assert!(matches!(template.nodes()[1], Node::ConditionalSpace { .. }));
+
+        // Whitespace between ~ and the variable is allowed
+        let template = parse("~  {$USER}").unwrap();
+        assert!(matches!(template.nodes()[0], Node::ConditionalSpace { .. }));
+
+        // Before a literal or plain text: literal tilde
+        let template = parse("~{\"text\":red}").unwrap();
+        assert!(matches!(&template.nodes()[0], Node::Text { text, .. } if text == "~"));
+        let template = parse("a~b").unwrap();
+        assert_eq!(
+            template.nodes(),
+            &[Node::Text { text: "a~b".to_string(), span: Span::new(0, 3) }]
+        );
+    }
+
+    #[test]
+    fn test_parse_groups() {
+        let template = parse("{cwd}[ on {git_branch:magenta}[ {git_tracking}]]").unwrap();
+        let Node::Group { nodes, span, .. } = &template.nodes()[1] else {
+            panic!("expected group");
+        };
+        assert_eq!(*span, Span::new(5, 48));
+        assert_eq!(nodes.len(), 3);
+        assert!(matches!(nodes[2], Node::Group { .. }));
+
+        // Variables inside (nested) groups are discovered
+        assert_eq!(template.variables(), vec!["cwd", "git_branch", "git_tracking"]);
+
+        // ~ before a group is a conditional space
+        let template = parse("{cwd}~[{git_branch}]").unwrap();
+        assert!(matches!(template.nodes()[1], Node::ConditionalSpace { .. }));
+
+        // Escaped brackets are literal text
+        let template = parse("\\[{cwd}\\]").unwrap();
+        assert!(matches!(&template.nodes()[0], Node::Text { text, .. } if text == "["));
+    }
+
+    #[test]
+    fn test_parse_group_errors() {
+        let err = parse("$ [{cwd}").unwrap_err();
+        assert_eq!(err.message, "unclosed '[' (use \\[ for a literal bracket)");
+        assert_eq!((err.line, err.column), (1, 3));
+
+        // A stray ']' is pointed at, not taken as text
+        let err = parse("{cwd}] $").unwrap_err();
+        assert_eq!(err.message, "unexpected ']' (use \\] for a literal bracket)");
+        assert_eq!((err.line, err.column), (1, 6));
+        assert!(parse("[[{cwd}]").is_err());
+    }
+
+    #[test]
+    fn test_parse_fill() {
+        let template = parse("{cwd} {fill:\"─\":bright_black} {time}").unwrap();
+        let fill = &template.nodes()[2];
+        let Node::Fill { text, style, span } = fill else {
+            panic!("expected fill, got {:?}", fill);
+        };
+        assert_eq!(text, "─");
+        assert_eq!(style.as_ref().unwrap().parts, vec!["bright_black"]);
+        assert_eq!(*span, Span::new(6, 31));
+        // Fills are not variables, but their styles are validated
+        assert_eq!(template.variables(), vec!["cwd", "time"]);
+        assert_eq!(template.styles().count(), 1);
+
+        let template = parse("[{fill}{fill:bg:blue}]").unwrap();
+        let Node::Group { nodes, .. } = &template.nodes()[0] else {
+            panic!("expected group");
+        };
+        assert!(matches!(&nodes[0], Node::Fill { text, style: None, .. } if text == " "));
+        assert!(matches!(&nodes[1], Node::Fill { style: Some(s), .. } if s.parts == ["bg:blue"]));
+
+        // A filtered `fill` is an ordinary variable
+        assert_eq!(parse("{fill|upper}").unwrap().variables(), vec!["fill"]);
+
+        assert_eq!(parse("{fill:\"\"}").unwrap_err().message, "fill text must not be empty");
+        assert_eq!(parse("{fill:\"-\" x}").unwrap_err().message, "expected ':' or '}' after fill");
+    }
+
+    #[test]
+    fn test_parse_priorities() {
+        let template = parse("{cwd:green} {ip_address:yellow!low} {battery_percentage!7}").unwrap();
+        assert_eq!(placeholder(&template, 0).priority, None);
+        let p = placeholder(&template, 1);
+        assert_eq!(p.priority, Some(1));
+        assert_eq!(p.span, Span::new(12, 35));
+        assert_eq!(template.priorities(), vec![1, 7]);
+
+        // After fallbacks the priority belongs to the whole placeholder
+        let template = parse(r#"{git_branch?"-":dim!high}"#).unwrap();
+        assert_eq!(placeholder(&template, 0).priority, Some(3));
+        assert_eq!(placeholder(&template, 1).priority, None);
+
+        // Groups take a priority right after their `]`
+        let template = parse("{cwd}[ on {git_branch!2}]!medium $").unwrap();
+        let Node::Group { priority, span, .. } = &template.nodes()[1] else {
+            panic!("expected group");
+        };
+        assert_eq!(*priority, Some(2));
+        assert_eq!(*span, Span::new(5, 32));
+        assert!(matches!(&template.nodes()[2], Node::Text { text, span } if text == " $" && span.start == 32));
+        assert_eq!(template.priorities(), vec![2, 2]);
+
+        // Anything else after `]` stays text
+        for source in ["[{cwd}]!", "[{cwd}]! x", "[{cwd}]!important"] {
+            let template = parse(source).unwrap();
+            assert!(matches!(&template.nodes()[0], Node::Group { priority: None, .. }), "{}", source);
+            assert_eq!(template.nodes().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_parse_priority_errors() {
+        let expected = "expected priority after '!' (low, medium, high or 0-255)";
+        assert_eq!(parse("{cwd!}").unwrap_err().message, expected);
+        assert_eq!(parse("{cwd!lowest}").unwrap_err().message, expected);
+        assert_eq!(parse("{cwd!300}").unwrap_err().message, expected);
+        assert_eq!(parse("{cwd!low:red}").unwrap_err().message, "expected '}' after priority");
+        assert_eq!(parse("{cwd:red x}").unwrap_err().message, "expected '?', '!' or '}' after style");
+    }
+
+    #[test]
+    fn test_drop_part() {
+        let mut template = parse("{a!1}[ {b!2} {c}]!3 {d!1}").unwrap();
+        assert_eq!(template.priorities(), vec![1, 3, 2, 1]);
+
+        // Dropped parts become empty groups
+        template.drop_part(3);
+        assert_eq!(template.priorities(), vec![1, 3, 2]);
+        assert!(matches!(&template.nodes()[3], Node::Group { nodes, priority: None, .. } if nodes.is_empty()));
+
+        // Dropping a group drops what it contains
+        template.drop_part(1);
+        assert_eq!(template.priorities(), vec![1]);
+        assert_eq!(template.variables(), vec!["a"]);
+    }
+
+    fn fragments(pairs: &[(&str, &str)]) -> Fragments {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_fragments() {
+        let fragments = fragments(&[("git", "{git_branch:magenta}{@dirty}"), ("dirty", "~{git_status_unstaged}")]);
+        let template = Template::parse_with("{cwd} {@git}!", &fragments).unwrap();
+        assert_eq!(template.variables(), vec!["cwd", "git_branch", "git_status_unstaged"]);
+        // Spliced nodes point at the reference
+        let p = placeholder(&template, 1);
+        assert_eq!(p.span, Span::new(6, 12));
+        assert_eq!(p.style.as_ref().unwrap().span, Span::new(6, 12));
+        assert!(matches!(template.nodes()[3], Node::ConditionalSpace { .. }));
+        assert!(matches!(&template.nodes()[5], Node::Text { text, .. } if text == "!"));
+
+        // Without fragments a reference is an error
+        assert_eq!(parse("{@git}").unwrap_err().message, "unknown fragment '@git'");
+    }
+
+    #[test]
+    fn test_parse_fragment_errors() {
+        let cyclic = fragments(&[("a", "{@b}"), ("b", "{cwd} {@a}"), ("self", "{@self}")]);
+        let err = Template::parse_with("x {@a}", &cyclic).unwrap_err();
+        assert_eq!(err.message, "fragment cycle: @a -> @b -> @a");
+        assert_eq!(err.span, Span::new(2, 6));
+        assert_eq!(err.fragment.as_deref(), Some("b"));
+        assert_eq!(Template::parse_with("{@self}", &cyclic).unwrap_err().message, "fragment cycle: @self -> @self");
+
+        // Errors inside a fragment name it and point at the reference
+        let broken = fragments(&[("git", "{git_branch"), ("outer", "{@git}")]);
+        let err = Template::parse_with("{cwd} {@outer}", &broken).unwrap_err();
+        assert_eq!(err.message, "in fragment '@git': unclosed '{' at line 1, column 1");
+        assert_eq!(err.span, Span::new(6, 14));
+        assert_eq!(err.fragment.as_deref(), Some("git"));
+        assert_eq!(Template::parse_with("{@git:red}", &broken).unwrap_err().fragment, None);
+
+        assert_eq!(parse("{@}").unwrap_err().message, "empty fragment name");
+        assert_eq!(
+            Template::parse_with("{@git:red}", &broken).unwrap_err().message,
+            "expected '}' after fragment reference"
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        // Basic variable extraction
+        let template = parse("{cwd} {git_branch}").unwrap();
+        assert_eq!(template.variables(), vec!["cwd", "git_branch"]);
+
+        // With colors
+        let template = parse("{cwd:green} {git_branch:magenta}").unwrap();
+        assert_eq!(template.variables(), vec!["cwd", "git_branch"]);
+
+        // Literals are excluded
+        let template = parse("{cwd} {\"!\": bold}").unwrap();
+        assert_eq!(template.variables(), vec!["cwd"]);
+
+        // Environment variables are excluded
+        let template = parse("{cwd} {$USER}").unwrap();
+        assert_eq!(template.variables(), vec!["cwd"]);
+
+        // Duplicates are removed
+        let template = parse("{cwd} {cwd:red}").unwrap();
+        assert_eq!(template.variables(), vec!["cwd"]);
+
+        // Complex prompt
+        let template = parse(
+            "{time:cyan} {hostname:yellow} {cwd:green}~{git_branch:magenta}{git_status_clean:green}",
+        )
+        .unwrap();
+        assert_eq!(
+            template.variables(),
+            vec!["time", "hostname", "cwd", "git_branch", "git_status_clean"]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("{}").is_err());
+        assert!(parse("{cwd").is_err());
+        assert!(parse("{cwd:}").is_err());
+        assert!(parse("{cwd:red,}").is_err());
+        assert!(parse("{cwd time}").is_err());
+    }
+
+    #[test]
+    fn test_error_position() {
+        let err = parse("line one\nab {cwd").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.column, 4);
+        assert_eq!(err.to_string(), "unclosed '{' at line 2, column 4");
+    }
+
+    #[test]
+    fn test_text_fallback() {
+        let template = Template::text("{broken");
+        assert_eq!(template.variables(), Vec::<&str>::new());
+        assert!(matches!(&template.nodes()[0], Node::Text { text, .. } if text == "{broken"));
+    }
+}
@@ -0,0 +1,572 @@
+// twig/src/template/parser.rs
+
+use super::lexer::{self, Token, TokenKind};
+use super::{parse_priority, Filter, Fragments, Node, Placeholder, Priority, Source, Span, StyleSpec, TemplateError};
+
+/// Recursive-descent parser turning a token stream into template nodes
+pub struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    fragments: &'a Fragments,
+    /// Fragments being expanded, outermost first (for cycle detection)
+    active: Vec<String>,
+    /// Whether a variable follows the reference being expanded, for a `~`
+    /// at the end of the fragment
+    variable_after: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str, tokens: Vec<Token>, fragments: &'a Fragments) -> Self {
+        Self {
+            source,
+            tokens,
+            pos: 0,
+            fragments,
+            active: Vec::new(),
+            variable_after: false,
+        }
+    }
+
+    /// Parse as the expansion of fragment `name`
+    pub fn expanding(mut self, name: &str) -> Self {
+        self.active.push(name.to_string());
+        self
+    }
+
+    /// Parse as text followed by a variable (or not)
+    fn followed_by_variable(mut self, variable_after: bool) -> Self {
+        self.variable_after = variable_after;
+        self
+    }
+
+    /// Parse the whole token stream
+    pub fn parse(mut self) -> Result<Vec<Node>, TemplateError> {
+        self.parse_nodes(None)
+    }
+
+    /// Parse nodes until the end of input, or until the `]` closing the
+    /// group that started at `group_start`
+    fn parse_nodes(&mut self, group_start: Option<usize>) -> Result<Vec<Node>, TemplateError> {
+        let mut nodes = Vec::new();
+
+        while let Some(token) = self.next() {
+            match token.kind {
+                TokenKind::Text(text) => push_text(&mut nodes, text, token.span),
+                TokenKind::Tilde => {
+                    // A tilde is only a conditional space when a variable or group
+                    // follows it; otherwise it is kept as a literal "~"
+                    if self.variable_follows() {
+                        nodes.push(Node::ConditionalSpace { span: token.span });
+                    } else {
+                        push_text(&mut nodes, "~".to_string(), token.span);
+                    }
+                }
+                TokenKind::Open if self.fragment_follows() => {
+                    for node in self.parse_fragment(token.span.start)? {
+                        match node {
+                            Node::Text { text, span } => push_text(&mut nodes, text, span),
+                            node => nodes.push(node),
+                        }
+                    }
+                }
+                TokenKind::Open if self.fill_follows() => {
+                    nodes.push(self.parse_fill(token.span.start)?);
+                }
+                TokenKind::Open => {
+                    let placeholder = self.parse_placeholder(token.span.start)?;
+                    nodes.push(Node::Placeholder(placeholder));
+                }
+                TokenKind::GroupOpen => {
+                    let inner = self.parse_nodes(Some(token.span.start))?;
+                    let mut end = self.tokens[self.pos - 1].span.end;
+                    let priority = self.eat_group_priority().map(|(priority, priority_end)| {
+                        end = priority_end;
+                        priority
+                    });
+                    nodes.push(Node::Group {
+                        nodes: inner,
+                        priority,
+                        span: Span::new(token.span.start, end),
+                    });
+                }
+                TokenKind::GroupClose => {
+                    return match group_start {
+                        Some(_) => Ok(nodes),
+                        None => Err(self.error("unexpected ']' (use \\] for a literal bracket)", token.span)),
+                    };
+                }
+                _ => {
+                    // The lexer never emits placeholder tokens outside of braces
+                    return Err(self.error("unexpected token", token.span));
+                }
+            }
+        }
+
+        match group_start {
+            Some(start) => Err(self.error("unclosed '[' (use \\[ for a literal bracket)", Span::new(start, self.source.len()))),
+            None => Ok(nodes),
+        }
+    }
+
+    /// Parse `alternative[?alternative...]}` after the opening brace
+    fn parse_placeholder(&mut self, start: usize) -> Result<Placeholder, TemplateError> {
+        let mut placeholder = self.parse_alternative(start, false)?;
+
+        loop {
+            let token = self.expect("unclosed '{'", start)?;
+            match token.kind {
+                TokenKind::Close => {
+                    placeholder.span = Span::new(start, token.span.end);
+                    return Ok(placeholder);
+                }
+                TokenKind::Question => {
+                    let fallback = self.parse_alternative(start, true)?;
+                    placeholder.fallbacks.push(fallback);
+                }
+                TokenKind::Bang => {
+                    placeholder.priority = Some(self.parse_priority(token.span)?);
+                    let close = self.expect("unclosed '{'", start)?;
+                    if close.kind != TokenKind::Close {
+                        return Err(self.error("expected '}' after priority", close.span));
+                    }
+                    placeholder.span = Span::new(start, close.span.end);
+                    return Ok(placeholder);
+                }
+                _ => {
+                    let styled = placeholder.fallbacks.last().unwrap_or(&placeholder).style.is_some();
+                    let message = if styled {
+                        "expected '?', '!' or '}' after style"
+                    } else {
+                        "expected '|', ':', '?', '!' or '}'"
+                    };
+                    return Err(self.error(message, token.span));
+                }
+            }
+        }
+    }
+
+    /// Parse the priority word after a `!`
+    fn parse_priority(&mut self, bang: Span) -> Result<Priority, TemplateError> {
+        const EXPECTED: &str = "expected priority after '!' (low, medium, high or 0-255)";
+        match self.next() {
+            Some(Token { kind: TokenKind::Word(word), span }) => {
+                parse_priority(&word).ok_or_else(|| self.error(EXPECTED, span))
+            }
+            Some(token) => Err(self.error(EXPECTED, token.span)),
+            None => Err(self.error(EXPECTED, bang)),
+        }
+    }
+
+    /// Consume a `!priority` written right after a group's `]`
+    ///
+    /// Outside braces `!` is text, so this splits it off the following text
+    /// token; anything else (`]!`, `]! `, `]!important`) stays text.
+    /// Returns the priority and the offset where it ends.
+    fn eat_group_priority(&mut self) -> Option<(Priority, usize)> {
+        let Some(Token { kind: TokenKind::Text(text), span }) = self.tokens.get_mut(self.pos) else {
+            return None;
+        };
+        let rest = text.strip_prefix('!')?;
+        let word_len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+        let priority = parse_priority(&rest[..word_len])?;
+
+        // The text token may hold resolved escapes, so its length can differ
+        // from the source; `!word` itself is always written literally
+        let consumed = 1 + word_len;
+        let end = span.start + consumed;
+        if consumed == text.len() {
+            self.pos += 1;
+        } else {
+            text.drain(..consumed);
+            span.start = end;
+        }
+        Some((priority, end))
+    }
+
+    /// Whether the placeholder being opened is a `{@name}` fragment reference
+    fn fragment_follows(&self) -> bool {
+        matches!(self.peek(), Some(Token { kind: TokenKind::Word(word), .. }) if word.starts_with('@'))
+    }
+
+    /// Parse `@name}` after the opening brace and return the fragment's nodes
+    fn parse_fragment(&mut self, start: usize) -> Result<Vec<Node>, TemplateError> {
+        let (word, word_span) = match self.next() {
+            Some(Token { kind: TokenKind::Word(word), span }) => (word, span),
+            _ => unreachable!("fragment_follows checked for a word"),
+        };
+        let close = self.expect("unclosed '{'", start)?;
+        if close.kind != TokenKind::Close {
+            return Err(self.error("expected '}' after fragment reference", close.span));
+        }
+        let span = Span::new(start, close.span.end);
+
+        let name = &word[1..];
+        if name.is_empty() {
+            return Err(self.error("empty fragment name", word_span));
+        }
+        if self.active.iter().any(|active| active == name) {
+            let chain: Vec<String> = self.active.iter().map(String::as_str).chain([name])
+                .map(|n| format!("@{}", n))
+                .collect();
+            // Found while expanding the innermost active fragment
+            let error = self.error(&format!("fragment cycle: {}", chain.join(" -> ")), span);
+            return Err(error.in_fragment(self.active.last().map_or(name, String::as_str)));
+        }
+        let Some(text) = self.fragments.get(name) else {
+            return Err(self.error(&format!("unknown fragment '@{}'", name), span));
+        };
+
+        // A `~` at the end of the fragment looks past the reference
+        let variable_after = self.variable_follows();
+        let parsed = lexer::tokenize(text).and_then(|tokens| {
+            Parser { active: self.active.clone(), ..Parser::new(text, tokens, self.fragments) }
+                .expanding(name)
+                .followed_by_variable(variable_after)
+                .parse()
+        });
+
+        match parsed {
+            Ok(mut nodes) => {
+                relocate(&mut nodes, span);
+                Ok(nodes)
+            }
+            // Errors from nested fragments already name their fragment
+            Err(TemplateError { message, fragment: Some(fragment), .. }) => {
+                Err(self.error(&message, span).in_fragment(&fragment))
+            }
+            Err(e) => Err(self.error(&format!("in fragment '@{}': {}", name, e), span).in_fragment(name)),
+        }
+    }
+
+    /// Whether the placeholder being opened is `{fill}`, `{fill:"text"...}` or `{fill:style}`
+    fn fill_follows(&self) -> bool {
+        let mut tokens = self.tokens[self.pos..].iter().map(|t| &t.kind);
+        matches!(tokens.next(), Some(TokenKind::Word(word)) if word == "fill")
+            && matches!(tokens.next(), Some(TokenKind::Close | TokenKind::Colon))
+    }
+
+    /// Parse `fill[:"text"][:style]}` after the opening brace
+    fn parse_fill(&mut self, start: usize) -> Result<Node, TemplateError> {
+        // `fill`
+        self.pos += 1;
+        let mut text = " ".to_string();
+        let mut style = None;
+
+        if let Some(colon_end) = self.eat_colon() {
+            match self.peek() {
+                Some(Token { kind: TokenKind::Str(fill), span }) => {
+                    if fill.is_empty() {
+                        return Err(self.error("fill text must not be empty", *span));
+                    }
+                    text = fill.clone();
+                    self.pos += 1;
+                    if let Some(colon_end) = self.eat_colon() {
+                        style = Some(self.parse_style(colon_end)?);
+                    }
+                }
+                _ => style = Some(self.parse_style(colon_end)?),
+            }
+        }
+
+        let token = self.expect("unclosed '{'", start)?;
+        match token.kind {
+            TokenKind::Close => Ok(Node::Fill {
+                text,
+                style,
+                span: Span::new(start, token.span.end),
+            }),
+            _ => Err(self.error("expected ':' or '}' after fill", token.span)),
+        }
+    }
+
+    /// Consume a `:` if one comes next, returning its end offset
+    fn eat_colon(&mut self) -> Option<usize> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Colon, span }) => {
+                let end = span.end;
+                self.pos += 1;
+                Some(end)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse one `source[|filter...][:style]` alternative
+    ///
+    /// The span covers the alternative itself; for the first alternative it is
+    /// widened to the whole placeholder by [`Self::parse_placeholder`].
+    fn parse_alternative(&mut self, start: usize, fallback: bool) -> Result<Placeholder, TemplateError> {
+        let token = self.expect("expected variable name or \"literal\"", start)?;
+        let source = match token.kind {
+            TokenKind::Str(text) => Source::Literal(text),
+            TokenKind::Word(word) => match word.strip_prefix('$') {
+                Some("") => return Err(self.error("empty environment variable name", token.span)),
+                Some(name) => Source::Env(name.to_string()),
+                None => Source::Variable(word),
+            },
+            TokenKind::Close if fallback => return Err(self.error("expected fallback after '?'", token.span)),
+            TokenKind::Close => return Err(self.error("empty placeholder", Span::new(start, token.span.end))),
+            _ => return Err(self.error("expected variable name or \"literal\"", token.span)),
+        };
+        let mut end = token.span.end;
+
+        let mut filters = Vec::new();
+        while let Some(Token { kind: TokenKind::Pipe, span }) = self.peek() {
+            let pipe = span.start;
+            self.pos += 1;
+            let filter = self.parse_filter(pipe)?;
+            end = filter.span.end;
+            filters.push(filter);
+        }
+
+        let mut style = None;
+        if let Some(Token { kind: TokenKind::Colon, span }) = self.peek() {
+            let colon_end = span.end;
+            self.pos += 1;
+            let spec = self.parse_style(colon_end)?;
+            end = spec.span.end;
+            style = Some(spec);
+        }
+
+        Ok(Placeholder {
+            source,
+            filters,
+            style,
+            fallbacks: Vec::new(),
+            priority: None,
+            span: Span::new(token.span.start, end),
+        })
+    }
+
+    /// Parse `name[(arg, "arg"...)]` after a `|`
+    fn parse_filter(&mut self, pipe: usize) -> Result<Filter, TemplateError> {
+        let (name, name_span) = match self.next() {
+            Some(Token { kind: TokenKind::Word(word), span }) => (word, span),
+            Some(token) => return Err(self.error("expected filter name after '|'", token.span)),
+            None => return Err(self.error("unclosed '{'", Span::new(pipe, self.source.len()))),
+        };
+
+        let mut args = Vec::new();
+        let mut end = name_span.end;
+        if matches!(self.peek(), Some(Token { kind: TokenKind::LParen, .. })) {
+            self.pos += 1;
+            loop {
+                match self.next() {
+                    Some(Token { kind: TokenKind::Word(arg) | TokenKind::Str(arg), .. }) => args.push(arg),
+                    Some(Token { kind: TokenKind::RParen, span }) if args.is_empty() => {
+                        end = span.end;
+                        break;
+                    }
+                    Some(token) => return Err(self.error("expected filter argument", token.span)),
+                    None => return Err(self.error("unclosed '('", Span::new(name_span.start, self.source.len()))),
+                }
+
+                match self.next() {
+                    Some(Token { kind: TokenKind::Comma, .. }) => {}
+                    Some(Token { kind: TokenKind::RParen, span }) => {
+                        end = span.end;
+                        break;
+                    }
+                    Some(token) => return Err(self.error("expected ',' or ')'", token.span)),
+                    None => return Err(self.error("unclosed '('", Span::new(name_span.start, self.source.len()))),
+                }
+            }
+        }
+
+        Ok(Filter {
+            name,
+            args,
+            span: Span::new(name_span.start, end),
+        })
+    }
+
+    /// Parse a comma-separated style list: `color[,style...]`
+    ///
+    /// Parts may take arguments, e.g. `rgb(180,142,173)` or `color(208)`,
+    /// and colors may carry a `bg:`/`fg:` prefix; parts are stored
+    /// normalized without whitespace.
+    fn parse_style(&mut self, start: usize) -> Result<StyleSpec, TemplateError> {
+        let mut parts = Vec::new();
+        let mut end;
+
+        loop {
+            let (mut part, span) = self.parse_style_part(start)?;
+            end = span.end;
+
+            // bg:<color> / fg:<color>
+            if (part == "bg" || part == "fg")
+                && matches!(self.peek(), Some(Token { kind: TokenKind::Colon, .. }))
+            {
+                self.pos += 1;
+                let (color, color_span) = self.parse_style_part(start)?;
+                part = format!("{}:{}", part, color);
+                end = color_span.end;
+            }
+
+            parts.push(part);
+
+            match self.peek() {
+                Some(Token { kind: TokenKind::Comma, .. }) => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        Ok(StyleSpec {
+            parts,
+            span: Span::new(start, end),
+        })
+    }
+
+    /// Parse one style word with optional arguments: `bold`, `rgb(1,2,3)`
+    fn parse_style_part(&mut self, start: usize) -> Result<(String, Span), TemplateError> {
+        match self.next() {
+            Some(Token { kind: TokenKind::Word(word), span }) => {
+                if matches!(self.peek(), Some(Token { kind: TokenKind::LParen, .. })) {
+                    self.pos += 1;
+                    let (args, close) = self.parse_style_args(span.start)?;
+                    Ok((format!("{}({})", word, args.join(",")), Span::new(span.start, close)))
+                } else {
+                    Ok((word, span))
+                }
+            }
+            Some(token) => Err(self.error("expected color or style", token.span)),
+            None => Err(self.error("unclosed '{'", Span::new(start, self.source.len()))),
+        }
+    }
+
+    /// Parse `arg[,arg...])` after the opening parenthesis of a style part
+    /// Returns the arguments and the end offset of the closing parenthesis
+    fn parse_style_args(&mut self, start: usize) -> Result<(Vec<String>, usize), TemplateError> {
+        let mut args = Vec::new();
+
+        loop {
+            match self.next() {
+                Some(Token { kind: TokenKind::Word(word), .. }) => args.push(word),
+                Some(Token { kind: TokenKind::RParen, span }) if args.is_empty() => {
+                    return Ok((args, span.end));
+                }
+                Some(token) => return Err(self.error("expected argument", token.span)),
+                None => return Err(self.error("unclosed '('", Span::new(start, self.source.len()))),
+            }
+
+            match self.next() {
+                Some(Token { kind: TokenKind::Comma, .. }) => {}
+                Some(Token { kind: TokenKind::RParen, span }) => return Ok((args, span.end)),
+                Some(token) => return Err(self.error("expected ',' or ')'", token.span)),
+                None => return Err(self.error("unclosed '('", Span::new(start, self.source.len()))),
+            }
+        }
+    }
+
+    /// Check whether the next placeholder (skipping whitespace-only text)
+    /// refers to a variable or environment variable, or a group follows
+    ///
+    /// Fragment references are looked into, as if their text were written in
+    /// place; past the end of a fragment, what follows its reference counts.
+    fn variable_follows(&self) -> bool {
+        self.leads_with_variable(&self.tokens[self.pos..], &mut self.active.clone())
+            .unwrap_or(self.variable_after)
+    }
+
+    /// Whether `tokens` start with a variable or group (skipping whitespace-only
+    /// text and blank fragments), or `None` if they run out first
+    ///
+    /// `seen` holds the fragments being looked into, so cycles end the search
+    /// (they are reported when the reference is parsed).
+    fn leads_with_variable(&self, tokens: &[Token], seen: &mut Vec<String>) -> Option<bool> {
+        let mut tokens = tokens.iter().map(|t| &t.kind);
+        loop {
+            match tokens.next()? {
+                TokenKind::Text(text) if text.trim().is_empty() => continue,
+                TokenKind::Open => match tokens.next() {
+                    Some(TokenKind::Word(word)) if word.starts_with('@') => {
+                        let name = &word[1..];
+                        if tokens.next() != Some(&TokenKind::Close) || seen.iter().any(|n| n == name) {
+                            return Some(false);
+                        }
+                        // Unknown and malformed fragments are reported when parsed
+                        let Some(Ok(fragment)) = self.fragments.get(name).map(|text| lexer::tokenize(text)) else {
+                            return Some(false);
+                        };
+                        seen.push(name.to_string());
+                        let leads = self.leads_with_variable(&fragment, seen);
+                        seen.pop();
+                        if leads.is_some() {
+                            return leads;
+                        }
+                    }
+                    Some(TokenKind::Word(_)) => return Some(true),
+                    _ => return Some(false),
+                },
+                TokenKind::GroupOpen => return Some(true),
+                _ => return Some(false),
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Take the next token or fail with `message` pointing at the open brace
+    fn expect(&mut self, message: &str, start: usize) -> Result<Token, TemplateError> {
+        match self.next() {
+            Some(token) => Ok(token),
+            None => Err(self.error(message, Span::new(start, self.source.len()))),
+        }
+    }
+
+    fn error(&self, message: &str, span: Span) -> TemplateError {
+        TemplateError::new(message, span, self.source)
+    }
+}
+
+/// Point every span in spliced fragment nodes at the `{@name}` reference
+fn relocate(nodes: &mut [Node], span: Span) {
+    for node in nodes {
+        match node {
+            Node::Text { span: node_span, .. } | Node::ConditionalSpace { span: node_span } => *node_span = span,
+            Node::Placeholder(placeholder) => relocate_placeholder(placeholder, span),
+            Node::Group { nodes, span: node_span, .. } => {
+                *node_span = span;
+                relocate(nodes, span);
+            }
+            Node::Fill { style, span: node_span, .. } => {
+                *node_span = span;
+                if let Some(style) = style {
+                    style.span = span;
+                }
+            }
+        }
+    }
+}
+
+fn relocate_placeholder(placeholder: &mut Placeholder, span: Span) {
+    placeholder.span = span;
+    if let Some(style) = &mut placeholder.style {
+        style.span = span;
+    }
+    for filter in &mut placeholder.filters {
+        filter.span = span;
+    }
+    for fallback in &mut placeholder.fallbacks {
+        relocate_placeholder(fallback, span);
+    }
+}
+
+/// Append text, merging with a preceding text node
+fn push_text(nodes: &mut Vec<Node>, text: String, span: Span) {
+    if let Some(Node::Text { text: prev, span: prev_span }) = nodes.last_mut() {
+        prev.push_str(&text);
+        prev_span.end = span.end;
+        return;
+    }
+    nodes.push(Node::Text { text, span });
+}
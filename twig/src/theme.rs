@@ -0,0 +1,129 @@
+// twig/src/theme.rs
+
+//! Theme files: reusable `[styles]` palettes
+//!
+//! `theme = "nord"` in config.toml loads `themes/nord.toml` next to the config
+//! file, falling back to the themes bundled with twig. A theme may also be
+//! given as a path (`theme = "~/dotfiles/twig/dark.toml"`).
+//!
+//! A theme file holds a single `[styles]` table in the same format as the
+//! config's own; entries in the config override the theme's.
+
+use crate::style::Palette;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Themes shipped with twig
+const BUILTIN_THEMES: [(&str, &str); 1] = [("nord", include_str!("../themes/nord.toml"))];
+
+#[derive(Debug, Deserialize)]
+struct ThemeFile {
+    #[serde(default)]
+    styles: Palette,
+}
+
+/// Load a theme's palette by name or path
+///
+/// # Arguments
+/// * `name` - Theme name (`"nord"`) or path to a `.toml` file
+/// * `config_dir` - Directory of the config file; named themes are looked up in its `themes/` subdirectory
+pub fn load(name: &str, config_dir: &Path) -> Result<Palette, String> {
+    let path = theme_path(name, config_dir);
+
+    let contents = if path.exists() {
+        fs::read_to_string(&path).map_err(|e| format!("Failed to read theme '{}': {}", path.display(), e))?
+    } else if let Some((_, builtin)) = BUILTIN_THEMES.iter().find(|(n, _)| *n == name) {
+        builtin.to_string()
+    } else {
+        let available: Vec<&str> = BUILTIN_THEMES.iter().map(|(n, _)| *n).collect();
+        return Err(format!(
+            "Theme '{}' not found (looked for {}; built-in themes: {})",
+            name,
+            path.display(),
+            available.join(", ")
+        ));
+    };
+
+    parse(&contents).map_err(|e| format!("Failed to parse theme '{}': {}", name, e))
+}
+
+/// Parse the contents of a theme file
+fn parse(contents: &str) -> Result<Palette, String> {
+    toml::from_str::<ThemeFile>(contents)
+        .map(|theme| theme.styles)
+        .map_err(|e| e.to_string())
+}
+
+/// Where a theme is looked up on disk
+fn theme_path(name: &str, config_dir: &Path) -> PathBuf {
+    if name.ends_with(".toml") || name.contains('/') {
+        // Explicit path, `~/` expanded
+        match name.strip_prefix("~/") {
+            Some(rest) => std::env::var("HOME")
+                .map(|home| Path::new(&home).join(rest))
+                .unwrap_or_else(|_| PathBuf::from(name)),
+            None => config_dir.join(name),
+        }
+    } else {
+        config_dir.join("themes").join(format!("{}.toml", name))
+    }
+}
+
+/// Merge a theme's palette under the config's own `[styles]`
+pub fn merge(styles: &mut Palette, theme: Palette) {
+    for (name, spec) in theme {
+        styles.entry(name).or_insert(spec);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_theme() {
+        let palette = load("nord", Path::new("/nonexistent")).unwrap();
+        assert_eq!(palette.get("vcs").map(String::as_str), Some("aurora_purple"));
+        assert_eq!(palette.get("aurora_purple").map(String::as_str), Some("#b48ead"));
+    }
+
+    #[test]
+    fn test_theme_file_overrides_builtin() {
+        let dir = std::env::temp_dir().join(format!("twig-theme-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("themes")).unwrap();
+        fs::write(dir.join("themes/nord.toml"), "[styles]\nvcs = \"magenta\"\n").unwrap();
+        fs::write(dir.join("custom.toml"), "[styles]\nwarn = \"red,bold\"\n").unwrap();
+
+        let palette = load("nord", &dir).unwrap();
+        assert_eq!(palette.get("vcs").map(String::as_str), Some("magenta"));
+
+        // Paths are relative to the config directory
+        let palette = load("custom.toml", &dir).unwrap();
+        assert_eq!(palette.get("warn").map(String::as_str), Some("red,bold"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_and_invalid_themes() {
+        let err = load("nope", Path::new("/nonexistent")).unwrap_err();
+        assert!(err.contains("Theme 'nope' not found"));
+        assert!(err.contains("nord"));
+
+        assert!(parse("[styles]\nwarn = 3\n").is_err());
+        assert_eq!(parse("").unwrap(), Palette::new());
+    }
+
+    #[test]
+    fn test_merge_keeps_config_entries() {
+        let mut styles = Palette::from([("warn".to_string(), "red".to_string())]);
+        let theme = Palette::from([
+            ("warn".to_string(), "yellow".to_string()),
+            ("vcs".to_string(), "magenta".to_string()),
+        ]);
+        merge(&mut styles, theme);
+        assert_eq!(styles["warn"], "red");
+        assert_eq!(styles["vcs"], "magenta");
+    }
+}
@@ -0,0 +1,167 @@
+// twig/src/width.rs
+
+//! Display width of text in terminal columns
+//!
+//! Everything that lines text up — narrow/wide switching, right prompts,
+//! `{fill}`, the `truncate`/`pad_*` filters and the `--debug` boxes —
+//! measures through [`display_width`], so they all agree on how wide a
+//! string is.
+//!
+//! Text is split into grapheme clusters (what the user sees as one
+//! character) and each cluster is measured by its East Asian Width:
+//! CJK and emoji take two columns, combining marks and zero-width joiners
+//! take none, and a ZWJ emoji sequence such as 👩‍💻 is a single two-column
+//! cluster.
+//!
+//! "Ambiguous" characters (`±`, `→`, box drawing, Greek, Cyrillic, …) are
+//! one column in most terminals but two in CJK locales. That is set with
+//! `ambiguous_width` in `[prompt]`:
+//!
+//! ```toml
+//! [prompt]
+//! ambiguous_width = "wide"   # default: "narrow"
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// How wide East Asian "ambiguous" characters are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmbiguousWidth {
+    /// One column (most terminals)
+    #[default]
+    Narrow,
+    /// Two columns (CJK terminals and locales)
+    Wide,
+}
+
+/// Policy used by [`display_width`], set once from the config
+static AMBIGUOUS_WIDE: AtomicBool = AtomicBool::new(false);
+
+/// Set the ambiguous-width policy for the rest of the run
+pub fn set_ambiguous_width(policy: AmbiguousWidth) {
+    AMBIGUOUS_WIDE.store(policy == AmbiguousWidth::Wide, Ordering::Relaxed);
+}
+
+fn ambiguous_width() -> AmbiguousWidth {
+    if AMBIGUOUS_WIDE.load(Ordering::Relaxed) {
+        AmbiguousWidth::Wide
+    } else {
+        AmbiguousWidth::Narrow
+    }
+}
+
+/// Columns `s` takes in the terminal (no escape codes expected)
+pub fn display_width(s: &str) -> usize {
+    display_width_with(s, ambiguous_width())
+}
+
+/// Columns `s` takes with an explicit ambiguous-width policy
+pub fn display_width_with(s: &str, policy: AmbiguousWidth) -> usize {
+    s.graphemes(true).map(|g| grapheme_width(g, policy)).sum()
+}
+
+fn grapheme_width(grapheme: &str, policy: AmbiguousWidth) -> usize {
+    match policy {
+        AmbiguousWidth::Narrow => grapheme.width(),
+        AmbiguousWidth::Wide => grapheme.width_cjk(),
+    }
+}
+
+/// Longest prefix of `s` that fits in `columns`, never splitting a grapheme
+pub fn truncate(s: &str, columns: usize) -> &str {
+    let policy = ambiguous_width();
+    let mut used = 0;
+    for (index, grapheme) in s.grapheme_indices(true) {
+        used += grapheme_width(grapheme, policy);
+        if used > columns {
+            return &s[..index];
+        }
+    }
+    s
+}
+
+/// Repeat `pattern` to fill exactly `columns`
+///
+/// When the next grapheme would overflow (a wide character with one column
+/// left) the rest is padded with spaces.
+pub fn repeat(pattern: &str, columns: usize) -> String {
+    let policy = ambiguous_width();
+    let graphemes: Vec<(&str, usize)> = pattern
+        .graphemes(true)
+        .map(|g| (g, grapheme_width(g, policy)))
+        .filter(|(_, width)| *width > 0)
+        .collect();
+
+    let mut output = String::new();
+    let mut used = 0;
+    for (grapheme, width) in graphemes.iter().cycle() {
+        if used + width > columns {
+            break;
+        }
+        output.push_str(grapheme);
+        used += width;
+    }
+    output.extend(std::iter::repeat_n(' ', columns - used));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn narrow(s: &str) -> usize {
+        display_width_with(s, AmbiguousWidth::Narrow)
+    }
+
+    #[test]
+    fn test_east_asian_width() {
+        assert_eq!(narrow("main"), 4);
+        assert_eq!(narrow("プロジェクト"), 12);
+        assert_eq!(narrow("~/文档"), 6);
+        assert_eq!(narrow("✔"), 1);
+        assert_eq!(narrow("✅"), 2);
+    }
+
+    #[test]
+    fn test_grapheme_clusters() {
+        // e + combining acute accent
+        assert_eq!(narrow("cafe\u{301}"), 4);
+        // woman + ZWJ + laptop
+        assert_eq!(narrow("👩\u{200d}💻"), 2);
+        // thumbs up + skin tone modifier
+        assert_eq!(narrow("👍🏽"), 2);
+        // stopwatch + emoji presentation selector
+        assert_eq!(narrow("⏱\u{fe0f}"), 2);
+        assert_eq!(narrow("\u{200b}"), 0);
+    }
+
+    #[test]
+    fn test_ambiguous_width() {
+        assert_eq!(display_width_with("±→", AmbiguousWidth::Narrow), 2);
+        assert_eq!(display_width_with("±→", AmbiguousWidth::Wide), 4);
+        // Unambiguous text is the same under both policies
+        assert_eq!(display_width_with("日本 ab", AmbiguousWidth::Wide), 7);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("feature", 4), "feat");
+        assert_eq!(truncate("日本語", 3), "日");
+        assert_eq!(truncate("cafe\u{301}s", 4), "cafe\u{301}");
+        assert_eq!(truncate("short", 10), "short");
+    }
+
+    #[test]
+    fn test_repeat() {
+        assert_eq!(repeat("─", 4), "────");
+        assert_eq!(repeat("-=", 5), "-=-=-");
+        // A wide character that doesn't fit is replaced by padding
+        assert_eq!(repeat("日", 5), "日日 ");
+        assert_eq!(repeat("\u{200b}", 2), "  ");
+        assert_eq!(repeat("x", 0), "");
+    }
+}